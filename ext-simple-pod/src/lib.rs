@@ -3,14 +3,13 @@ use k8s_openapi::{
     apimachinery::pkg::apis::meta::v1::ObjectMeta,
 };
 use kube::{
-    api::{ListParams, Meta, PostParams},
+    api::{DeleteParams, ListParams, Meta, PostParams},
     Api, Client, CustomResource
 };
 use kube_runtime::controller::{Context, Controller, ReconcilerAction};
-use futures::task::SpawnExt;
+use kube_runtime::finalizer::{self, finalizer, Event};
 
 use serde::{Deserialize, Serialize};
-use std::ops::Deref;
 use futures::StreamExt;
 use std::time::{Duration};
 use snafu::Snafu;
@@ -34,8 +33,11 @@ pub struct SimplePodSpec {
 
 // TODO: Add status?
 
+/// Finalizer deleting the pod of the `SimplePod`
+const FINALIZER: &str = "simplepods.slinky.dev/cleanup";
+
 /// The controller triggers this on reconcile errors
-fn error_policy(_error: &Error, _ctx: Context<Data>) -> ReconcilerAction {
+fn error_policy(_error: &finalizer::Error<Error>, _ctx: Context<Data>) -> ReconcilerAction {
     ReconcilerAction {
         requeue_after: Some(Duration::from_secs(1)),
     }
@@ -46,15 +48,7 @@ struct Data {
     client: Client,
 }
 
-#[no_mangle]
-pub extern "C" fn run() {
-    let exec = kube::abi::get_mut_executor();
-    // Start the main
-    exec.deref().borrow_mut().spawner().spawn(main()).unwrap();
-    // Give a little push to the executor
-    exec.deref().borrow_mut().run_until_stalled();
-}
-
+#[kube::abi::main]
 async fn main() {
     let client = Client::default();
 
@@ -71,10 +65,21 @@ async fn main() {
 }
 
 /// Controller triggers this whenever our main object or our children changed
-async fn reconcile(simple_pod: SimplePod, ctx: Context<Data>) -> Result<ReconcilerAction, Error> {
+async fn reconcile(simple_pod: SimplePod, ctx: Context<Data>) -> Result<ReconcilerAction, finalizer::Error<Error>> {
     let client = ctx.get_ref().client.clone();
-    let pods: Api<Pod> = Api::namespaced(client.clone(), "default");
+    let simple_pods: Api<SimplePod> = Api::namespaced(client.clone(), "default");
+    let pods: Api<Pod> = Api::namespaced(client, "default");
+
+    finalizer(&simple_pods, FINALIZER, simple_pod, |event| async move {
+        match event {
+            Event::Apply(simple_pod) => apply(simple_pod, &pods).await,
+            Event::Cleanup(simple_pod) => cleanup(simple_pod, &pods).await,
+        }
+    }).await
+}
 
+/// Create the pod of the `SimplePod`, or update its image
+async fn apply(simple_pod: SimplePod, pods: &Api<Pod>) -> Result<ReconcilerAction, Error> {
     let name = simple_pod.name();
     let image = simple_pod.spec.image;
 
@@ -111,8 +116,20 @@ async fn reconcile(simple_pod: SimplePod, ctx: Context<Data>) -> Result<Reconcil
     })
 }
 
+/// Delete the pod of the `SimplePod` being deleted
+async fn cleanup(simple_pod: SimplePod, pods: &Api<Pod>) -> Result<ReconcilerAction, Error> {
+    match pods.delete(&simple_pod.name(), &DeleteParams::default()).await {
+        Ok(_) => println!("Deleting pod"),
+        Err(kube::Error::Api(ae)) if ae.code == 404 => println!("Pod already deleted"),
+        Err(e) => Err(Error::UnknownKubeError { source: e })?,
+    };
+
+    Ok(ReconcilerAction {
+        requeue_after: None,
+    })
+}
+
 fn pod(name: &str, image: &str) -> Pod {
-    // TODO: Add ownerRef for deletion handling.
     Pod {
         metadata: ObjectMeta {
             name: Some(name.to_string()),
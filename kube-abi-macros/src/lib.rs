@@ -0,0 +1,86 @@
+//! Entry point macro of the modules built with the `kube` guest library
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, spanned::Spanned, ItemFn};
+
+/// Marks the `async fn` the module runs when the host starts it.
+///
+/// It generates the exports the host calls: `run`, the `wakeup_*` functions, `allocate`,
+/// `deallocate` and `abi_descriptor`, all of them driving the [`kube::abi::Runtime`] of the module.
+///
+/// ```rust,ignore
+/// #[kube::abi::main]
+/// async fn main() {
+///     let client = Client::default();
+///     // ...
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn main(args: TokenStream, item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as ItemFn);
+
+    if !args.is_empty() {
+        return syn::Error::new(proc_macro2::Span::call_site(), "the main function doesn't accept arguments")
+            .to_compile_error()
+            .into();
+    }
+    if input.sig.asyncness.is_none() {
+        return syn::Error::new(input.sig.fn_token.span(), "the main function must be async")
+            .to_compile_error()
+            .into();
+    }
+    if !input.sig.inputs.is_empty() {
+        return syn::Error::new(input.sig.inputs.span(), "the main function cannot have parameters")
+            .to_compile_error()
+            .into();
+    }
+
+    let name = &input.sig.ident;
+    let expanded = quote! {
+        #input
+
+        #[no_mangle]
+        pub extern "C" fn run() {
+            ::kube::abi::exports::run(#name())
+        }
+
+        #[no_mangle]
+        pub extern "C" fn wakeup_future(future_id: u64, ptr: *const u8, len: usize) {
+            ::kube::abi::exports::wakeup_future(future_id, ptr, len)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn wakeup_stream(stream_id: u64, ptr: *const u8, len: usize) {
+            ::kube::abi::exports::wakeup_stream(stream_id, ptr, len)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn wakeup_stream_batch(stream_id: u64, ptr: *const u8, len: usize) {
+            ::kube::abi::exports::wakeup_stream_batch(stream_id, ptr, len)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn wakeup_batch(ptr: *const u8, len: usize) {
+            ::kube::abi::exports::wakeup_batch(ptr, len)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn allocate(size: usize) -> *mut ::std::ffi::c_void {
+            ::kube::abi::exports::allocate(size)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn deallocate(ptr: *mut ::std::ffi::c_void, size: usize) {
+            ::kube::abi::exports::deallocate(ptr, size)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn abi_descriptor() -> u64 {
+            ::kube::abi::exports::abi_descriptor()
+        }
+    };
+    expanded.into()
+}
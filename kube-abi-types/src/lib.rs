@@ -0,0 +1,6 @@
+//! Types exchanged between the host and the modules through the abi.
+//!
+//! Each abi version has its own module: a wire type is never changed once released,
+//! a new abi version gets new types instead.
+
+pub mod v1alpha1;
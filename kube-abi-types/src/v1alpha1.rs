@@ -0,0 +1,158 @@
+//! Wire types of the `rust_v1alpha1` abi, encoded with bincode.
+
+use serde::{Deserialize, Serialize};
+
+/// The Kubernetes resource targeted by a request
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Resource {
+    /// The API version of the resource, eg "apps/v1" or "v1"
+    pub api_version: String,
+    /// The group of the resource, or the empty string for the core group
+    pub group: String,
+    /// The kind of the resource
+    pub kind: String,
+    /// The version of the resource
+    pub version: String,
+    /// The namespace of the resource, if namespaced
+    pub namespace: Option<String>,
+}
+
+/// Query parameters of list and watch calls, mirroring `kube::api::ListParams`
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ListParams {
+    pub label_selector: Option<String>,
+    pub field_selector: Option<String>,
+    pub timeout: Option<u32>,
+    pub allow_bookmarks: bool,
+    pub limit: Option<u32>,
+    pub continue_token: Option<String>,
+}
+
+/// Payload of the `kube-watch-abi` `watch` import
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WatchRequest {
+    pub resource: Resource,
+    pub resource_version: String,
+    pub list_params: ListParams,
+}
+
+/// Query to run against the cache shared by the host
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum CacheRequestVerb {
+    Get { name: String },
+    List { label_selector: Option<String>, field_selector: Option<String> },
+}
+
+/// Payload of the `kube-cache-abi` `query` import
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CacheRequest {
+    pub resource: Resource,
+    pub verb: CacheRequestVerb,
+}
+
+/// Payload of the `http-proxy-abi` imports
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HttpRequest {
+    #[serde(with = "http_serde::method")]
+    pub method: http::Method,
+
+    #[serde(with = "http_serde::uri")]
+    pub uri: http::Uri,
+
+    #[serde(with = "http_serde::header_map")]
+    pub headers: http::HeaderMap,
+
+    pub body: Vec<u8>,
+}
+
+impl From<http::Request<Vec<u8>>> for HttpRequest {
+    fn from(req: http::Request<Vec<u8>>) -> Self {
+        let (parts, body) = req.into_parts();
+
+        HttpRequest {
+            method: parts.method,
+            uri: parts.uri,
+            headers: parts.headers,
+            body,
+        }
+    }
+}
+
+impl From<HttpRequest> for http::Request<Vec<u8>> {
+    fn from(req: HttpRequest) -> Self {
+        let mut request = http::Request::new(req.body);
+        *request.method_mut() = req.method;
+        *request.uri_mut() = req.uri;
+        *request.headers_mut() = req.headers;
+        request
+    }
+}
+
+/// Payload of the `http-proxy-abi` imports, sent instead of `HttpRequest` by the modules declaring
+/// the `request-timeout` feature
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TimedHttpRequest {
+    pub request: HttpRequest,
+    /// Overrides the default request timeout of the host
+    pub timeout: Option<std::time::Duration>,
+}
+
+/// Response to a proxied http request, without the body when it's the head of a streamed response
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HttpResponse {
+    #[serde(with = "http_serde::status_code")]
+    pub status_code: http::StatusCode,
+
+    #[serde(with = "http_serde::header_map")]
+    pub headers: http::HeaderMap,
+
+    pub body: Vec<u8>,
+}
+
+impl From<http::Response<Vec<u8>>> for HttpResponse {
+    fn from(res: http::Response<Vec<u8>>) -> Self {
+        let (parts, body) = res.into_parts();
+
+        HttpResponse {
+            status_code: parts.status,
+            headers: parts.headers,
+            body,
+        }
+    }
+}
+
+impl From<HttpResponse> for http::Response<Vec<u8>> {
+    fn from(res: HttpResponse) -> Self {
+        let mut response = http::Response::new(res.body);
+        *response.status_mut() = res.status_code;
+        *response.headers_mut() = res.headers;
+        response
+    }
+}
+
+/// Failure of a proxied http request, when no response could be received from the API server.
+///
+/// To the modules declaring the `http-errors` feature, the `http-proxy-abi` replies with a bincode encoded
+/// `Result<HttpResponse, HttpError>`, while the body chunks of a streamed response are encoded as `Result<Vec<u8>, HttpError>`.
+/// The other modules get the bare `HttpResponse`, with the failures turned into `Status` responses, and the raw chunks.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum HttpError {
+    /// The request cannot be sent, because it's malformed
+    InvalidRequest(String),
+    /// The connection with the API server failed, eg connection reset, DNS or TLS failures
+    Transport(String),
+    /// No response was received within the timeout of the request
+    Timeout(std::time::Duration),
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpError::InvalidRequest(message) => write!(f, "invalid request: {}", message),
+            HttpError::Transport(message) => write!(f, "transport error: {}", message),
+            HttpError::Timeout(timeout) => write!(f, "request timed out after {:?}", timeout),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
@@ -10,16 +10,19 @@ use crate::{
 };
 use derivative::Derivative;
 use futures::{
-    channel, future,
+    channel,
     stream::{self, SelectAll},
-    FutureExt, SinkExt, Stream, StreamExt, TryFuture, TryFutureExt, TryStream, TryStreamExt,
+    SinkExt, Stream, StreamExt, TryFuture, TryFutureExt, TryStream, TryStreamExt,
 };
 use kube::api::{Api, ListParams, Meta};
 use serde::de::DeserializeOwned;
 use snafu::{futures::TryStreamExt as SnafuTryStreamExt, Backtrace, OptionExt, ResultExt, Snafu};
 use std::{sync::Arc, time::Duration};
 use stream::BoxStream;
-use std::time::Instant;
+use crate::time::Instant;
+use runner::Runner;
+
+mod runner;
 
 #[derive(Snafu, Debug)]
 pub enum Error<ReconcilerErr: std::error::Error + 'static, QueueErr: std::error::Error + 'static> {
@@ -129,6 +132,10 @@ impl<T> Context<T> {
 /// usually taken from a `reflector` and then passed through a trigger function such as
 /// `trigger_self`.
 ///
+/// Up to `max_concurrent_reconciles` objects are reconciled at the same time (`0` means no limit),
+/// but the same object is never reconciled twice at once: if it's triggered while its reconciliation
+/// is running, it's reconciled once more right after.
+///
 /// This is the "hard-mode" version of `Controller`, which allows you some more customization
 /// (such as triggering from arbitrary `Stream`s), at the cost of some more verbosity.
 pub fn applier<K, QueueStream, ReconcilerFut, T>(
@@ -137,6 +144,7 @@ pub fn applier<K, QueueStream, ReconcilerFut, T>(
     context: Context<T>,
     store: Store<K>,
     queue: QueueStream,
+    max_concurrent_reconciles: usize,
 ) -> impl Stream<Item = Result<(ObjectRef<K>, ReconcilerAction), Error<ReconcilerFut::Error, QueueStream::Error>>>
 where
     K: Clone + Meta + 'static,
@@ -148,7 +156,7 @@ where
     let err_context = context.clone();
     let (scheduler_tx, scheduler_rx) = channel::mpsc::channel::<ScheduleRequest<ObjectRef<K>>>(100);
     // Create a stream of ObjectRefs that need to be reconciled
-    trystream_try_via(
+    let reconcile_requests = trystream_try_via(
         // input: stream combining scheduled tasks and user specified inputs event
         Box::pin(stream::select(
             // 1. inputs from users queue stream
@@ -161,24 +169,23 @@ where
         )),
         // all the Oks from the select gets passed through the scheduler stream
         |s| scheduler(s).context(SchedulerDequeueFailed),
-    )
-    // now have ObjectRefs that we turn into pairs inside (no extra waiting introduced)
-    .and_then(move |obj_ref| {
-        future::ready(
-            store
-                .get(&obj_ref)
-                .context(ObjectNotFound {
-                    obj_ref: obj_ref.clone(),
-                })
-                .map(|obj| (obj_ref, obj)),
-        )
-    })
-    // then reconcile every object
-    .and_then(move |(obj_ref, obj)| {
-        reconciler(obj, context.clone()) // TODO: add a context argument to the reconcile
-            .into_future() // TryFuture -> impl Future
-            .map(|result| (obj_ref, result)) // turn into pair and ok wrap
-            .map(Ok) // (this lets us deal with errors from reconciler below)
+    );
+    // then reconcile every object, several at a time but never the same object twice at once
+    Runner::new(reconcile_requests, max_concurrent_reconciles, move |obj_ref| {
+        // look the object up only once its turn comes, so that reruns see its latest version
+        let reconciler_fut = store
+            .get(&obj_ref)
+            .context(ObjectNotFound {
+                obj_ref: obj_ref.clone(),
+            })
+            .map(|obj| reconciler(obj, context.clone()));
+        async move {
+            match reconciler_fut {
+                // turn into pair and ok wrap (this lets us deal with errors from reconciler below)
+                Ok(reconciler_fut) => Ok((obj_ref, reconciler_fut.into_future().await)),
+                Err(err) => Err(err),
+            }
+        }
     })
     // finally, for each completed reconcile call:
     .and_then(move |(obj_ref, reconciler_result)| {
@@ -280,6 +287,7 @@ where
     // TODO: get an arbitrary std::error::Error in here?
     selector: SelectAll<BoxStream<'static, Result<ObjectRef<K>, watcher::Error>>>,
     reader: Store<K>,
+    max_concurrent_reconciles: usize,
 }
 
 impl<K> Controller<K>
@@ -297,7 +305,11 @@ where
         let self_watcher =
             trigger_self(try_flatten_applied(reflector(writer, watcher(owned_api, lp)))).boxed();
         selector.push(self_watcher);
-        Self { selector, reader }
+        Self {
+            selector,
+            reader,
+            max_concurrent_reconciles: 0,
+        }
     }
 
     /// Retrieve a copy of the reader before starting the controller
@@ -305,6 +317,14 @@ where
         self.reader.clone()
     }
 
+    /// Limit how many objects are reconciled at the same time
+    ///
+    /// By default there's no limit. Regardless of the limit, the same object is never reconciled twice at once.
+    pub fn max_concurrent_reconciles(mut self, limit: usize) -> Self {
+        self.max_concurrent_reconciles = limit;
+        self
+    }
+
     /// Indicate child objets `K` owns and be notified when they change
     ///
     /// This type `Child` must have `OwnerReference`s set to point back to `K`.
@@ -357,7 +377,14 @@ where
         ReconcilerFut: TryFuture<Ok = ReconcilerAction>,
         ReconcilerFut::Error: std::error::Error + 'static,
     {
-        applier(reconciler, error_policy, context, self.reader, self.selector)
+        applier(
+            reconciler,
+            error_policy,
+            context,
+            self.reader,
+            self.selector,
+            self.max_concurrent_reconciles,
+        )
     }
 }
 
@@ -0,0 +1,217 @@
+use futures::{
+    future::{self, Join, Ready},
+    stream::{Fuse, FusedStream, FuturesUnordered},
+    Future, Stream, StreamExt,
+};
+use pin_project::pin_project;
+use std::{
+    collections::HashSet,
+    hash::Hash,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Runs the futures started by `run_msg` for each message of `input`, up to `max_concurrent` at a time.
+///
+/// Futures of the same message never overlap: if the message is received again while its future is
+/// still running, it is run once more as soon as the current future completes.
+/// Receiving the message several times while it runs still triggers a single rerun.
+///
+/// Errors from `input` are passed through, without starting anything.
+#[pin_project(project = RunnerProj)]
+pub(crate) struct Runner<T, S, F, Fut>
+where
+    Fut: Future,
+{
+    #[pin]
+    input: Fuse<S>,
+    run_msg: F,
+    /// Pending futures, each one paired with its message.
+    ///
+    /// To ensure that `running_msgs` is kept up-to-date, use `start` rather than pushing here directly.
+    running: FuturesUnordered<Join<Ready<T>, Fut>>,
+    /// Messages with a running future.
+    running_msgs: HashSet<T>,
+    /// Messages received while their future was running, to rerun once it completes.
+    rerun_msgs: HashSet<T>,
+    /// Maximum number of futures running at the same time, `0` means unbounded.
+    max_concurrent: usize,
+}
+
+impl<T, S, F, Fut> Runner<T, S, F, Fut>
+where
+    S: Stream,
+    Fut: Future,
+{
+    pub(crate) fn new(input: S, max_concurrent: usize, run_msg: F) -> Self {
+        Self {
+            input: input.fuse(),
+            run_msg,
+            running: FuturesUnordered::new(),
+            running_msgs: HashSet::new(),
+            rerun_msgs: HashSet::new(),
+            max_concurrent,
+        }
+    }
+}
+
+impl<T, S, F, Fut> RunnerProj<'_, T, S, F, Fut>
+where
+    T: Eq + Hash + Clone,
+    F: FnMut(T) -> Fut,
+    Fut: Future,
+{
+    fn has_capacity(&self) -> bool {
+        *self.max_concurrent == 0 || self.running.len() < *self.max_concurrent
+    }
+
+    /// Start the future of `msg`, or queue a rerun if it's already running.
+    fn schedule(&mut self, msg: T) {
+        if self.running_msgs.contains(&msg) {
+            self.rerun_msgs.insert(msg);
+        } else {
+            self.start(msg);
+        }
+    }
+
+    fn start(&mut self, msg: T) {
+        let fut = (self.run_msg)(msg.clone());
+        self.running_msgs.insert(msg.clone());
+        self.running.push(future::join(future::ready(msg), fut));
+    }
+}
+
+impl<T, E, O, S, F, Fut> Stream for Runner<T, S, F, Fut>
+where
+    T: Eq + Hash + Clone,
+    S: Stream<Item = Result<T, E>>,
+    F: FnMut(T) -> Fut,
+    Fut: Future<Output = Result<O, E>>,
+{
+    type Item = Result<O, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.as_mut().project();
+
+        // Only accept new messages while there's room for them, leaving the rest in `input` as backpressure
+        while this.has_capacity() {
+            match this.input.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(msg))) => this.schedule(msg),
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                Poll::Ready(None) | Poll::Pending => break,
+            }
+        }
+
+        match this.running.poll_next_unpin(cx) {
+            Poll::Ready(Some((msg, output))) => {
+                this.running_msgs.remove(&msg);
+                if this.rerun_msgs.remove(&msg) {
+                    // The completed future just freed its slot, so the rerun can always start right away
+                    this.start(msg);
+                }
+                Poll::Ready(Some(output))
+            }
+            Poll::Ready(None) => {
+                if this.input.is_terminated() {
+                    // The input has terminated, and all the futures are done, so terminate
+                    Poll::Ready(None)
+                } else {
+                    // Nothing is running, but we may get more messages in the future...
+                    Poll::Pending
+                }
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Runner;
+    use futures::{
+        channel::{mpsc, oneshot},
+        future::LocalBoxFuture,
+        poll, stream, FutureExt, SinkExt, StreamExt,
+    };
+    use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+    /// Starts futures completing when their sender in `completions` is used, recording the started messages
+    fn controlled(
+        started: Rc<RefCell<Vec<u8>>>,
+        completions: Rc<RefCell<HashMap<u8, oneshot::Sender<()>>>>,
+    ) -> impl FnMut(u8) -> LocalBoxFuture<'static, Result<u8, ()>> {
+        move |msg| {
+            let (tx, rx) = oneshot::channel();
+            started.borrow_mut().push(msg);
+            completions.borrow_mut().insert(msg, tx);
+            rx.map(move |_| Ok(msg)).boxed_local()
+        }
+    }
+
+    #[test]
+    fn runner_should_run_different_messages_concurrently() {
+        futures::executor::block_on(async {
+            let started = Rc::new(RefCell::new(Vec::new()));
+            let completions = Rc::new(RefCell::new(HashMap::new()));
+            let mut runner = Runner::new(
+                stream::iter(vec![Ok::<_, ()>(1u8), Ok(2), Ok(3)]),
+                2,
+                controlled(started.clone(), completions.clone()),
+            );
+            assert!(poll!(runner.next()).is_pending());
+            // The third message waits for a free slot
+            assert_eq!(*started.borrow(), vec![1, 2]);
+            completions.borrow_mut().remove(&2).unwrap().send(()).unwrap();
+            assert_eq!(runner.next().await, Some(Ok(2)));
+            assert!(poll!(runner.next()).is_pending());
+            assert_eq!(*started.borrow(), vec![1, 2, 3]);
+            completions.borrow_mut().remove(&1).unwrap().send(()).unwrap();
+            completions.borrow_mut().remove(&3).unwrap().send(()).unwrap();
+            let mut rest = vec![runner.next().await, runner.next().await];
+            rest.sort();
+            assert_eq!(rest, vec![Some(Ok(1)), Some(Ok(3))]);
+            // Stream has terminated
+            assert!(runner.next().await.is_none());
+        });
+    }
+
+    #[test]
+    fn runner_should_rerun_message_received_while_running_once() {
+        futures::executor::block_on(async {
+            let started = Rc::new(RefCell::new(Vec::new()));
+            let completions = Rc::new(RefCell::new(HashMap::new()));
+            let (mut input_tx, input_rx) = mpsc::unbounded();
+            let mut runner = Runner::new(input_rx, 0, controlled(started.clone(), completions.clone()));
+            input_tx.send(Ok::<_, ()>(1u8)).await.unwrap();
+            assert!(poll!(runner.next()).is_pending());
+            input_tx.send(Ok(1)).await.unwrap();
+            input_tx.send(Ok(1)).await.unwrap();
+            assert!(poll!(runner.next()).is_pending());
+            // The message never runs twice at the same time
+            assert_eq!(*started.borrow(), vec![1]);
+            completions.borrow_mut().remove(&1).unwrap().send(()).unwrap();
+            assert_eq!(runner.next().await, Some(Ok(1)));
+            // Both triggers received while running are folded into a single rerun
+            assert_eq!(*started.borrow(), vec![1, 1]);
+            drop(input_tx);
+            completions.borrow_mut().remove(&1).unwrap().send(()).unwrap();
+            assert_eq!(runner.next().await, Some(Ok(1)));
+            assert!(runner.next().await.is_none());
+            assert_eq!(*started.borrow(), vec![1, 1]);
+        });
+    }
+
+    #[test]
+    fn runner_should_pass_input_errors_through() {
+        futures::executor::block_on(async {
+            let mut runner = Runner::new(
+                stream::iter(vec![Err("failed"), Ok(1u8)]),
+                1,
+                |msg| async move { Ok(msg) },
+            );
+            assert_eq!(runner.next().await, Some(Err("failed")));
+            assert_eq!(runner.next().await, Some(Ok(1)));
+            assert!(runner.next().await.is_none());
+        });
+    }
+}
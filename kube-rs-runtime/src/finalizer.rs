@@ -0,0 +1,151 @@
+use crate::controller::ReconcilerAction;
+use futures::{TryFuture, TryFutureExt};
+use kube::{
+    api::{Meta, PatchParams, PatchStrategy},
+    Api,
+};
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use snafu::{Backtrace, ResultExt, Snafu};
+
+#[derive(Snafu, Debug)]
+pub enum Error<ReconcileErr: std::error::Error + 'static> {
+    #[snafu(display("failed to apply object: {}", source))]
+    ApplyFailed {
+        source: ReconcileErr,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("failed to clean up object: {}", source))]
+    CleanupFailed {
+        source: ReconcileErr,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("failed to add finalizer: {}", source))]
+    AddFinalizer {
+        source: kube::Error,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("failed to remove finalizer: {}", source))]
+    RemoveFinalizer {
+        source: kube::Error,
+        backtrace: Backtrace,
+    },
+}
+
+/// What the reconciler should do with the object
+#[derive(Debug)]
+pub enum Event<K> {
+    /// The object is alive: reconcile it as usual
+    ///
+    /// The finalizer is already registered, so the object can't go away before `Cleanup` succeeds.
+    Apply(K),
+    /// The object is being deleted: clean up anything that won't be garbage collected through `ownerReferences`,
+    /// like external state
+    ///
+    /// The finalizer is removed once the cleanup succeeds, otherwise the cleanup is retried following the
+    /// `error_policy`, so it must be idempotent.
+    Cleanup(K),
+}
+
+/// Reconcile an object guarded by the finalizer `finalizer_name`
+///
+/// Live objects get the finalizer added, if missing, before being passed to `reconcile` as `Event::Apply`.
+/// Objects being deleted are passed to `reconcile` as `Event::Cleanup`, and get the finalizer removed
+/// once it succeeds. Objects being deleted without the finalizer have already been cleaned up, so they're skipped.
+///
+/// The finalizers are updated with JSON merge patches, through `api`, which must have the scope of the object.
+/// The patches carry the resource version of `obj`, so they fail if the object changed in the meantime:
+/// the reconciliation is then retried on the newer version.
+///
+/// ```no_run
+/// use kube::{api::Meta, Api, Client};
+/// use kube_runtime::controller::{Context, ReconcilerAction};
+/// use kube_runtime::finalizer::{finalizer, Event};
+/// use k8s_openapi::api::core::v1::ConfigMap;
+/// use snafu::Snafu;
+///
+/// #[derive(Debug, Snafu)]
+/// enum Error {}
+///
+/// async fn reconcile(cm: ConfigMap, ctx: Context<Client>) -> Result<ReconcilerAction, kube_runtime::finalizer::Error<Error>> {
+///     let cms: Api<ConfigMap> = Api::namespaced(ctx.get_ref().clone(), &Meta::namespace(&cm).unwrap());
+///     finalizer(&cms, "configmaps.nullable.se/cleanup", cm, |event| async move {
+///         match event {
+///             Event::Apply(cm) => {
+///                 // .. create the external state of the ConfigMap
+///             }
+///             Event::Cleanup(cm) => {
+///                 // .. delete the external state of the ConfigMap
+///             }
+///         }
+///         Ok::<_, Error>(ReconcilerAction { requeue_after: None })
+///     })
+///     .await
+/// }
+/// ```
+pub async fn finalizer<K, ReconcileFut>(
+    api: &Api<K>,
+    finalizer_name: &str,
+    obj: K,
+    reconcile: impl FnOnce(Event<K>) -> ReconcileFut,
+) -> Result<ReconcilerAction, Error<ReconcileFut::Error>>
+where
+    K: Clone + Meta + DeserializeOwned,
+    ReconcileFut: TryFuture<Ok = ReconcilerAction>,
+    ReconcileFut::Error: std::error::Error + 'static,
+{
+    let being_deleted = obj.meta().deletion_timestamp.is_some();
+    let has_finalizer = obj
+        .meta()
+        .finalizers
+        .iter()
+        .flatten()
+        .any(|finalizer| finalizer == finalizer_name);
+
+    match (being_deleted, has_finalizer) {
+        (false, true) => reconcile(Event::Apply(obj)).into_future().await.context(ApplyFailed),
+        (false, false) => {
+            // Add the finalizer before touching anything, so nothing created by `reconcile` can be left behind
+            match patch_finalizers(api, &obj, |finalizers| finalizers.push(finalizer_name.to_string())).await {
+                Ok(obj) => reconcile(Event::Apply(obj)).into_future().await.context(ApplyFailed),
+                Err(err) => Err(err).context(AddFinalizer),
+            }
+        }
+        (true, true) => {
+            if let Err(err) = reconcile(Event::Cleanup(obj.clone())).into_future().await {
+                return Err(err).context(CleanupFailed);
+            }
+            patch_finalizers(api, &obj, |finalizers| {
+                finalizers.retain(|finalizer| finalizer != finalizer_name)
+            })
+            .await
+            .context(RemoveFinalizer)
+            // The object is going away, so there's nothing left to requeue
+            .map(|_| ReconcilerAction { requeue_after: None })
+        }
+        (true, false) => Ok(ReconcilerAction { requeue_after: None }),
+    }
+}
+
+/// Update the finalizers of `obj` with a JSON merge patch, returning the patched object
+///
+/// A merge patch replaces the whole list, so the resource version is sent along
+/// to make the API server reject the patch if the list changed in the meantime.
+async fn patch_finalizers<K>(api: &Api<K>, obj: &K, update: impl FnOnce(&mut Vec<String>)) -> kube::Result<K>
+where
+    K: Clone + Meta + DeserializeOwned,
+{
+    let mut finalizers = obj.meta().finalizers.clone().unwrap_or_default();
+    update(&mut finalizers);
+    let patch = json!({
+        "metadata": {
+            "finalizers": finalizers,
+            "resourceVersion": obj.meta().resource_version,
+        }
+    });
+    let pp = PatchParams {
+        patch_strategy: PatchStrategy::Merge,
+        ..PatchParams::default()
+    };
+    api.patch(&obj.name(), &pp, serde_json::to_vec(&patch)?).await
+}
@@ -16,14 +16,16 @@
 #![allow(clippy::default_trait_access)]
 
 pub mod controller;
+pub mod finalizer;
 pub mod reflector;
 pub mod scheduler;
 pub mod utils;
 pub mod watcher;
 
-pub(crate) mod time;
+pub mod time;
 
 pub use controller::{applier, Controller};
+pub use finalizer::finalizer;
 pub use reflector::reflector;
 pub use scheduler::scheduler;
 pub use watcher::watcher;
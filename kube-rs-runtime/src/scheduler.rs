@@ -15,7 +15,7 @@ use crate::time::{
     delay_queue::{self, Expired, DelayQueue}
 };
 
-use std::time::Instant;
+use crate::time::Instant;
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -1,8 +1,10 @@
 use std::future::Future;
 use std::pin::Pin;
 use std::task::{self, Poll};
-use std::time::{Duration, Instant};
+use std::time::Duration;
+use super::Instant;
 use std::fmt;
+use kube::abi::HostTimer;
 
 /// Waits until `deadline` is reached.
 ///
@@ -57,10 +59,8 @@ pub fn sleep(duration: Duration) -> Delay {
 /// [`sleep_until`](sleep_until).
 #[must_use = "futures do nothing unless you `.await` or poll them"]
 pub struct Delay {
-    /// The link between the `Delay` instance and the timer that drives it.
-    ///
-    /// This also stores the `deadline` value.
-    fut: Pin<Box<dyn Future<Output=()> + Send>>,
+    /// The host timer driving the `Delay`, or `None` if the deadline was already reached.
+    timer: Option<HostTimer>,
 
     deadline: Instant
 }
@@ -70,11 +70,11 @@ impl Delay {
         let now = Instant::now();
         match deadline.checked_duration_since(now) {
             Some(dur) => Delay {
-                fut: Box::pin(kube::abi::register_delay(dur)),
+                timer: Some(HostTimer::new(dur)),
                 deadline
             },
             None => Delay {
-                fut: Box::pin(futures::future::ready(())),
+                timer: None,
                 deadline: now
             }
         }
@@ -92,8 +92,16 @@ impl Delay {
         self.deadline.checked_duration_since(Instant::now()).is_none()
     }
 
+    /// Resets the `Delay` to complete at the new deadline, re-arming the host timer
+    /// instead of leaving the old one running.
     pub fn reset(&mut self, deadline: Instant) {
-        *self = Delay::new_timeout(deadline);
+        match (&mut self.timer, deadline.checked_duration_since(Instant::now())) {
+            (Some(timer), Some(dur)) => {
+                timer.reset(dur);
+                self.deadline = deadline;
+            }
+            _ => *self = Delay::new_timeout(deadline),
+        }
     }
 }
 
@@ -101,7 +109,10 @@ impl Future for Delay {
     type Output = ();
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Self::Output> {
-        self.fut.as_mut().poll(cx)
+        match &mut self.timer {
+            Some(timer) => Pin::new(timer).poll(cx),
+            None => Poll::Ready(()),
+        }
     }
 }
 
@@ -14,7 +14,8 @@ use std::future::Future;
 use std::marker::PhantomData;
 use std::pin::Pin;
 use std::task::{self, Poll};
-use std::time::{Instant, Duration};
+use std::time::Duration;
+use super::Instant;
 use futures::ready;
 
 /// A queue of delayed elements.
@@ -0,0 +1,113 @@
+use std::ops::{Add, AddAssign, Sub};
+use std::time::Duration;
+
+thread_local! {
+    // Origin of the instants when the host doesn't provide its clock
+    static ORIGIN: std::time::Instant = std::time::Instant::now();
+}
+
+/// A measurement of the monotonic clock of the host.
+///
+/// Unlike `std::time::Instant`, it follows the host virtual time when enabled,
+/// so it's consistent with the delays served by the host. When the host doesn't
+/// provide the `time-abi`, it falls back to the WASI clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(Duration);
+
+impl Instant {
+    /// Returns an instant corresponding to "now".
+    #[must_use]
+    pub fn now() -> Instant {
+        Instant(kube::abi::monotonic_now().unwrap_or_else(|| ORIGIN.with(std::time::Instant::elapsed)))
+    }
+
+    /// Returns the amount of time elapsed from another instant to this one,
+    /// or `None` if that instant is later than this one.
+    #[must_use]
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        self.0.checked_sub(earlier.0)
+    }
+
+    /// Returns the amount of time elapsed from another instant to this one,
+    /// or zero duration if that instant is later than this one.
+    #[must_use]
+    pub fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    /// Returns the amount of time elapsed since this instant was created.
+    #[must_use]
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().saturating_duration_since(*self)
+    }
+
+    /// Returns `Some(t)` where `t` is the time `self + duration` if `t` can be represented,
+    /// `None` otherwise.
+    #[must_use]
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(duration).map(Instant)
+    }
+
+    /// Returns `Some(t)` where `t` is the time `self - duration` if `t` can be represented,
+    /// `None` otherwise.
+    ///
+    /// The clock starts at the start of the host, or at the first read of the WASI clock,
+    /// so instants before that cannot be represented.
+    #[must_use]
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_sub(duration).map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, other: Duration) -> Instant {
+        Instant(self.0 + other)
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, other: Duration) {
+        self.0 += other;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    /// Saturates at the origin of the clock, which is close to the start of the module,
+    /// instead of panicking.
+    fn sub(self, other: Duration) -> Instant {
+        self.checked_sub(other).unwrap_or(Instant(Duration::from_secs(0)))
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, other: Instant) -> Duration {
+        self.saturating_duration_since(other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sub_saturates_at_the_origin() {
+        let early = Instant(Duration::from_millis(500));
+        assert_eq!(early.checked_sub(Duration::from_secs(1)), None);
+        assert_eq!(early - Duration::from_secs(1), Instant(Duration::from_secs(0)));
+        assert_eq!(early - Duration::from_millis(200), Instant(Duration::from_millis(300)));
+        assert_eq!(early.checked_sub(Duration::from_millis(500)), Some(Instant(Duration::from_secs(0))));
+    }
+
+    #[test]
+    fn checked_add_detects_overflows() {
+        let instant = Instant(Duration::from_secs(1));
+        assert_eq!(instant.checked_add(Duration::from_secs(1)), Some(Instant(Duration::from_secs(2))));
+        assert_eq!(instant.checked_add(Duration::new(u64::MAX, 0)), None);
+    }
+}
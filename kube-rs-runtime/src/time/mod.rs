@@ -1,13 +1,17 @@
+//! Clock and timers of the controllers, backed by the host
+
 // TODO these are took from tokio and adapted to our delay
 
 use std::time::Duration;
 
 mod error;
 mod delay;
+mod instant;
 pub(crate) mod delay_queue;
 pub(crate) mod wheel;
 
 pub(crate) use error::Error;
+pub use instant::Instant;
 
 enum Round {
     Up,
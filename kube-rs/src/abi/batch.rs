@@ -0,0 +1,159 @@
+//! Decoding of the batches the host hands over in a single wakeup.
+//!
+//! Frames cut off before their declared length are rejected: the host never sends them,
+//! so the decoding stops there rather than taking the bytes left as a value.
+
+/// Frame shorter than its declared length, at `offset` in the batch
+#[derive(Debug, PartialEq)]
+pub(crate) struct TruncatedFrame {
+    pub(crate) offset: usize,
+}
+
+/// Items of a stream batch, each one framed as a little endian u32 length followed by the item
+pub(crate) struct StreamBatch<'a> {
+    batch: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> StreamBatch<'a> {
+    pub(crate) fn new(batch: &'a [u8]) -> Self {
+        StreamBatch { batch, offset: 0 }
+    }
+}
+
+impl Iterator for StreamBatch<'_> {
+    type Item = Result<Vec<u8>, TruncatedFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.batch[self.offset..];
+        if rest.is_empty() {
+            return None;
+        }
+        let item = match frame_value(rest, 0, 4) {
+            Some(item) => item,
+            None => {
+                let offset = self.offset;
+                self.offset = self.batch.len();
+                return Some(Err(TruncatedFrame { offset }));
+            }
+        };
+        self.offset += 4 + item.len();
+        Some(Ok(item.to_vec()))
+    }
+}
+
+/// Result of an async request in a wakeup batch
+#[derive(Debug, PartialEq)]
+pub(crate) struct BatchEntry {
+    pub(crate) async_request_id: u64,
+    /// `true` for stream items, `false` for futures
+    pub(crate) stream: bool,
+    /// `None` completes a future without a value, or closes a stream
+    pub(crate) value: Option<Vec<u8>>,
+}
+
+/// Entries of a wakeup batch, each one framed as: the async request id as a little endian u64, a byte for the async type
+/// (`0` future, `1` stream), a byte telling if a value follows, the value length as a little endian u32 and the value
+pub(crate) struct WakeupBatch<'a> {
+    batch: &'a [u8],
+    offset: usize,
+}
+
+const ENTRY_HEADER_LEN: usize = 14;
+
+impl<'a> WakeupBatch<'a> {
+    pub(crate) fn new(batch: &'a [u8]) -> Self {
+        WakeupBatch { batch, offset: 0 }
+    }
+}
+
+impl Iterator for WakeupBatch<'_> {
+    type Item = Result<BatchEntry, TruncatedFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.batch[self.offset..];
+        if rest.is_empty() {
+            return None;
+        }
+        let value = match frame_value(rest, 10, ENTRY_HEADER_LEN) {
+            Some(value) => value,
+            None => {
+                let offset = self.offset;
+                self.offset = self.batch.len();
+                return Some(Err(TruncatedFrame { offset }));
+            }
+        };
+        self.offset += ENTRY_HEADER_LEN + value.len();
+
+        let mut id = [0; 8];
+        id.copy_from_slice(&rest[..8]);
+        let has_value = rest[9] != 0;
+        Some(Ok(BatchEntry {
+            async_request_id: u64::from_le_bytes(id),
+            stream: rest[8] != 0,
+            value: if has_value { Some(value.to_vec()) } else { None },
+        }))
+    }
+}
+
+/// The value of the frame, whose little endian u32 length is at `len_at` and which starts after `header_len` bytes.
+/// Returns `None` if the frame is truncated
+fn frame_value(frame: &[u8], len_at: usize, header_len: usize) -> Option<&[u8]> {
+    let len = frame.get(len_at..len_at + 4)?;
+    let value_len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+    frame.get(header_len..header_len.checked_add(value_len)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Same layout as the host `encode_batch` of `["ab", ""]`
+    const STREAM_BATCH: &[u8] = &[2, 0, 0, 0, b'a', b'b', 0, 0, 0, 0];
+
+    /// Same layout as the host `encode_wakeup_batch` of a future with value `"ok"` and a closed stream
+    const WAKEUP_BATCH: &[u8] = &[
+        7, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 0, 0, 0, b'o', b'k',
+        9, 1, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0,
+    ];
+
+    #[test]
+    fn decode_stream_batch() {
+        let items: Vec<_> = StreamBatch::new(STREAM_BATCH).collect();
+        assert_eq!(items, vec![Ok(b"ab".to_vec()), Ok(Vec::new())]);
+        assert_eq!(StreamBatch::new(&[]).count(), 0);
+    }
+
+    #[test]
+    fn decode_stream_batch_rejects_truncated_frames() {
+        // Cut off value
+        let items: Vec<_> = StreamBatch::new(&STREAM_BATCH[..5]).collect();
+        assert_eq!(items, vec![Err(TruncatedFrame { offset: 0 })]);
+        // Cut off length, after a complete frame
+        let items: Vec<_> = StreamBatch::new(&STREAM_BATCH[..8]).collect();
+        assert_eq!(items, vec![Ok(b"ab".to_vec()), Err(TruncatedFrame { offset: 6 })]);
+    }
+
+    #[test]
+    fn decode_wakeup_batch() {
+        let entries: Vec<_> = WakeupBatch::new(WAKEUP_BATCH).collect();
+        assert_eq!(
+            entries,
+            vec![
+                Ok(BatchEntry { async_request_id: 7, stream: false, value: Some(b"ok".to_vec()) }),
+                Ok(BatchEntry { async_request_id: 265, stream: true, value: None }),
+            ]
+        );
+    }
+
+    #[test]
+    fn decode_wakeup_batch_rejects_truncated_frames() {
+        // Cut off value
+        let entries: Vec<_> = WakeupBatch::new(&WAKEUP_BATCH[..15]).collect();
+        assert_eq!(entries, vec![Err(TruncatedFrame { offset: 0 })]);
+        // Cut off header, after a complete entry
+        let entries: Vec<_> = WakeupBatch::new(&WAKEUP_BATCH[..20]).collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1], Err(TruncatedFrame { offset: 16 }));
+    }
+}
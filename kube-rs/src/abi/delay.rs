@@ -1,16 +1,62 @@
 use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
 use std::time::Duration;
 use futures::FutureExt;
+use super::executor::{AbiFuture, Runtime};
+use super::host_supports;
 
 #[link(wasm_import_module = "delay-abi")]
 extern "C" {
     // Returns the future identifier
     fn delay(millis: u64) -> u64;
+    // Re-arms the timer, returning the identifier of the new future
+    fn reset(timer_id: u64, millis: u64) -> u64;
 }
 
 pub fn register_delay(del: Duration) -> impl Future<Output=()> + Send {
-    let millis = del.as_millis() as u64;
-    super::start_future(
-        unsafe { delay(millis) }
-    ).map(|v| ())
-}
\ No newline at end of file
+    HostTimer::new(del)
+}
+
+/// Timer served by the host, identified by its async request id.
+///
+/// Dropping it cancels the host timer.
+pub struct HostTimer {
+    id: u64,
+    fut: AbiFuture,
+}
+
+impl HostTimer {
+    pub fn new(del: Duration) -> HostTimer {
+        let id = unsafe { delay(del.as_millis() as u64) };
+        HostTimer {
+            id,
+            fut: super::start_future(id),
+        }
+    }
+
+    /// Re-arm the timer to fire after the provided duration, even if it already fired.
+    ///
+    /// The host replaces the timer with a new one, so a wakeup of the old timer already on its way is ignored.
+    pub fn reset(&mut self, del: Duration) {
+        if !host_supports("delay-abi.reset") {
+            *self = HostTimer::new(del);
+            return;
+        }
+        let id = unsafe { reset(self.id, del.as_millis() as u64) };
+        Runtime::with(|runtime| {
+            // The host already cancelled the old timer
+            runtime.forget(self.id);
+            self.fut = runtime.start_future(id);
+        });
+        self.id = id;
+    }
+}
+
+impl Future for HostTimer {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.fut.poll_unpin(cx).map(|_| ())
+    }
+}
@@ -0,0 +1,35 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// Descriptor of the abi this library is built against, checked by the host before running the module
+pub(crate) static DESCRIPTOR: &str = concat!(
+    r#"{"abi":"rust_v1alpha1","#,
+    r#""imports":["http-proxy-abi.request","http-proxy-abi.request_stream","delay-abi.delay","#,
+    r#""kube-watch-abi.watch","kube-cache-abi.query","async-abi.cancel","abi-info.supports","time-abi.now","#,
+    r#""delay-abi.reset"],"#,
+    r#""optionalImports":["abi-info.supports","time-abi.now","delay-abi.reset"],"#,
+    r#""features":["http-errors","request-timeout"]}"#
+);
+
+thread_local! {
+    static SUPPORTED: RefCell<HashMap<String, bool>> = RefCell::new(HashMap::new());
+}
+
+#[link(wasm_import_module = "abi-info")]
+extern "C" {
+    fn supports(ptr: *const u8, len: usize) -> u32;
+}
+
+/// Check if the host provides the capability, either an import like `delay-abi.delay` or an abi feature.
+/// Hosts not knowing `abi-info.supports` support none of the optional capabilities.
+pub fn host_supports(capability: &str) -> bool {
+    SUPPORTED.with(|supported| {
+        if let Some(value) = supported.borrow().get(capability) {
+            return *value;
+        }
+        // The host capabilities don't change while the module runs
+        let value = unsafe { supports(capability.as_ptr(), capability.len()) != 0 };
+        supported.borrow_mut().insert(capability.to_string(), value);
+        value
+    })
+}
@@ -1,149 +1,202 @@
-use std::collections::HashMap;
-use std::sync::{Mutex, Arc, Once};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, Arc};
 use std::pin::Pin;
 use std::future::Future;
 use std::task::{Context, Poll, Waker};
-use futures::executor::LocalPool;
-use std::cell::{RefCell};
-use std::ops::Deref;
-use std::mem;
-use std::rc::Rc;
+use futures::executor::{LocalPool, LocalSpawner};
+use futures::task::LocalSpawnExt;
+use std::cell::RefCell;
 use futures::Stream;
+use super::batch::{StreamBatch, WakeupBatch};
 
-pub fn get_mut_executor() -> Rc<RefCell<LocalPool>> {
-    // Initialize it to a null value
-    static mut SINGLETON: *const Rc<RefCell<LocalPool>> = 0 as *const Rc<RefCell<LocalPool>>;
-    static ONCE: Once = Once::new();
-
-    unsafe {
-        ONCE.call_once(|| {
-            // Make it
-            let singleton = Rc::new(RefCell::new(LocalPool::new()));
+#[link(wasm_import_module = "async-abi")]
+extern "C" {
+    // Tells the host the async request is not awaited anymore
+    fn cancel(async_request_id: u64);
+}
 
-            // Put it in the heap so it can outlive this call
-            SINGLETON = mem::transmute(Box::new(singleton));
-        });
+thread_local! {
+    // Modules are single threaded, so this is the only runtime of the module
+    static RUNTIME: Runtime = Runtime::new();
+}
 
-        (*SINGLETON).clone()
-    }
+/// Runtime of the module: it owns the executor running the module futures
+/// and the async requests waiting for the host to wake them up.
+///
+/// The exports generated by [`main`](crate::abi::main) drive it, so the controllers don't need to use it directly.
+pub struct Runtime {
+    pool: RefCell<LocalPool>,
+    spawner: LocalSpawner,
+    pending: RefCell<HashMap<u64, Arc<Mutex<AbiFutureState>>>>,
 }
 
-fn get_pending_futures() -> Rc<RefCell<HashMap<u64, Arc<Mutex<AbiFutureState>>>>> {
-    // Initialize it to a null value
-    static mut SINGLETON: *const Rc<RefCell<HashMap<u64, Arc<Mutex<AbiFutureState>>>>> = 0 as *const Rc<RefCell<HashMap<u64, Arc<Mutex<AbiFutureState>>>>>;
-    static ONCE: Once = Once::new();
+impl Runtime {
+    fn new() -> Runtime {
+        let pool = LocalPool::new();
+        let spawner = pool.spawner();
+        Runtime {
+            pool: RefCell::new(pool),
+            spawner,
+            pending: RefCell::new(HashMap::new()),
+        }
+    }
 
-    unsafe {
-        ONCE.call_once(|| {
-            // Make it
-            let singleton: Rc<RefCell<HashMap<u64, Arc<Mutex<AbiFutureState>>>>> = Rc::new(RefCell::new(HashMap::new()));
+    /// Run the closure with the runtime of the module
+    pub fn with<R>(f: impl FnOnce(&Runtime) -> R) -> R {
+        RUNTIME.with(f)
+    }
 
-            // Put it in the heap so it can outlive this call
-            SINGLETON = mem::transmute(Box::new(singleton));
-        });
+    /// Spawn a task, which runs the next time the executor runs
+    pub fn spawn(&self, future: impl Future<Output = ()> + 'static) {
+        self.spawner
+            .spawn_local(future)
+            .expect("The executor of the module is shut down")
+    }
 
-        (*SINGLETON).clone()
+    /// Run the tasks up to the point where all of them wait for the host
+    pub fn run_until_stalled(&self) {
+        self.pool.borrow_mut().run_until_stalled()
     }
-}
 
-pub fn start_future(future_id: u64) -> AbiFuture {
-    let state = Arc::new(Mutex::new(
-        AbiFutureState {
-            value: None,
-            completed: false,
-            waker: None
+    /// Track the async request started with the host, returning the future completed by its result
+    pub fn start_future(&self, future_id: u64) -> AbiFuture {
+        AbiFuture {
+            id: future_id,
+            shared_state: self.track(future_id),
         }
-    ));
-    get_pending_futures().deref().borrow_mut().insert(future_id, state.clone());
+    }
 
-    AbiFuture {
-        shared_state: state
+    /// Track the async request started with the host, returning the stream of its results
+    pub fn start_stream(&self, stream_id: u64) -> AbiStream {
+        AbiStream {
+            id: stream_id,
+            shared_state: self.track(stream_id),
+        }
     }
-}
 
-pub fn start_stream(stream_id: u64) -> AbiStream {
-    let state = Arc::new(Mutex::new(
-        AbiFutureState {
-            value: None,
+    fn track(&self, async_request_id: u64) -> Arc<Mutex<AbiFutureState>> {
+        let state = Arc::new(Mutex::new(AbiFutureState {
+            values: VecDeque::new(),
             completed: false,
-            waker: None
-        }
-    ));
-    get_pending_futures().deref().borrow_mut().insert(stream_id, state.clone());
+            waker: None,
+        }));
+        self.pending.borrow_mut().insert(async_request_id, state.clone());
+        state
+    }
 
-    AbiStream {
-        shared_state: state
+    /// Complete the future, with a value if any. Results of futures not awaited anymore are dropped
+    pub fn complete_future(&self, future_id: u64, value: Option<Vec<u8>>) {
+        let state_arc = match self.pending.borrow_mut().remove(&future_id) {
+            Some(state_arc) => state_arc,
+            // The future was dropped in the meantime
+            None => return,
+        };
+        let waker = {
+            let mut state = state_arc.lock().unwrap();
+            state.values.extend(value);
+            state.completed = true;
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake()
+        }
     }
-}
 
-#[no_mangle]
-pub extern "C" fn wakeup_future(future_id: u64, ptr: *const u8, len: usize) {
-    let fut_state = get_pending_futures();
-    let waker = {
-        let state_arc = fut_state.deref().borrow_mut().remove(&future_id).unwrap();
-        let mut state = state_arc.lock().unwrap();
-
-        if !ptr.is_null() {
-            state.value = Some(unsafe {
-                Vec::from_raw_parts(
-                    ptr as *mut u8,
-                    len as usize,
-                    len as usize,
-                )
-            });
+    /// Queue the items of the stream. Items of streams not awaited anymore are dropped
+    pub fn push_stream_items(&self, stream_id: u64, items: impl IntoIterator<Item = Vec<u8>>) {
+        let state_arc = match self.pending.borrow().get(&stream_id) {
+            Some(state_arc) => state_arc.clone(),
+            // The stream was dropped in the meantime
+            None => return,
+        };
+        let waker = {
+            let mut state = state_arc.lock().unwrap();
+            state.values.extend(items);
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
         }
+    }
 
-        state.completed = true;
-        state.waker.take()
-    };
-    if let Some(waker) = waker {
-        waker.wake()
+    /// End the stream, once its queued items are consumed
+    pub fn close_stream(&self, stream_id: u64) {
+        let state_arc = match self.pending.borrow_mut().remove(&stream_id) {
+            Some(state_arc) => state_arc,
+            None => return,
+        };
+        let waker = {
+            let mut state = state_arc.lock().unwrap();
+            state.completed = true;
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
     }
 
-    // Let's try to execute stuff up to the point where there isn't anything else to execute
-    get_mut_executor().deref().borrow_mut().run_until_stalled();
-}
+    /// Queue several items of the stream, framed as a little endian u32 length followed by the item.
+    /// A truncated frame ends the batch, as the host never sends them
+    pub fn push_stream_batch(&self, stream_id: u64, batch: &[u8]) {
+        let items = StreamBatch::new(batch).take_while(Result::is_ok).filter_map(Result::ok);
+        self.push_stream_items(stream_id, items);
+    }
 
-#[no_mangle]
-pub extern "C" fn wakeup_stream(stream_id: u64, ptr: *const u8, len: usize) {
-    let fut_state = get_pending_futures();
-    let waker = {
-        let mut states = fut_state.deref().borrow_mut();
-        let state_arc = states.remove(&stream_id).unwrap();
-        let mut state = state_arc.lock().unwrap();
-
-        let has_value = !ptr.is_null();
-        if has_value {
-            state.value = Some(unsafe {
-                Vec::from_raw_parts(
-                    ptr as *mut u8,
-                    len as usize,
-                    len as usize,
-                )
-            });
+    /// Apply the results of several futures and streams.
+    /// Each entry is framed as: the async request id as a little endian u64, a byte for the async type
+    /// (`0` future, `1` stream), a byte telling if a value follows, the value length as a little endian u32 and the value.
+    /// A truncated frame ends the batch, as the host never sends them
+    pub fn apply_batch(&self, batch: &[u8]) {
+        for entry in WakeupBatch::new(batch).take_while(Result::is_ok).filter_map(Result::ok) {
+            match (entry.stream, entry.value) {
+                (false, value) => self.complete_future(entry.async_request_id, value),
+                (true, Some(value)) => self.push_stream_items(entry.async_request_id, Some(value)),
+                (true, None) => self.close_stream(entry.async_request_id),
+            }
         }
-        state.completed = true;
-        let waker = state.waker.take();
-        if has_value {
-            states.insert(stream_id, state_arc.clone());
+    }
+
+    /// Stop tracking the async request, without notifying the host.
+    /// Its results are dropped, like the ones of cancelled requests
+    pub fn forget(&self, async_request_id: u64) {
+        self.pending.borrow_mut().remove(&async_request_id);
+    }
+
+    /// Remove the async request from the pending ones, notifying the host if it was still pending
+    fn cancel_if_pending(&self, async_request_id: u64) {
+        let pending = self.pending.borrow_mut().remove(&async_request_id);
+        if pending.is_some() {
+            unsafe { cancel(async_request_id) }
         }
-        waker
-    };
-    if let Some(waker) = waker {
-        waker.wake();
     }
+}
 
-    // Let's try to execute stuff up to the point where there isn't anything else to execute
-    get_mut_executor().deref().borrow_mut().run_until_stalled();
+/// Shorthand for [`Runtime::start_future`] on the runtime of the module
+pub fn start_future(future_id: u64) -> AbiFuture {
+    Runtime::with(|runtime| runtime.start_future(future_id))
+}
+
+/// Shorthand for [`Runtime::start_stream`] on the runtime of the module
+pub fn start_stream(stream_id: u64) -> AbiStream {
+    Runtime::with(|runtime| runtime.start_stream(stream_id))
 }
 
 pub struct AbiFuture {
+    id: u64,
     shared_state: Arc<Mutex<AbiFutureState>>,
 }
 
+impl Drop for AbiFuture {
+    fn drop(&mut self) {
+        Runtime::with(|runtime| runtime.cancel_if_pending(self.id))
+    }
+}
+
 /// Shared state between the future and the waiting thread
 struct AbiFutureState {
-    value: Option<Vec<u8>>,
+    /// Values received and not consumed yet: at most one for futures, any number of items for streams
+    values: VecDeque<Vec<u8>>,
+    /// The future completed, or the stream ended
     completed: bool,
 
     /// The waker for the task that `TimerFuture` is running on.
@@ -160,7 +213,7 @@ impl Future for AbiFuture {
         // Look at the shared state to see if the timer has already completed.
         let mut shared_state = self.shared_state.lock().unwrap();
         if shared_state.completed {
-            Poll::Ready(shared_state.value.take())
+            Poll::Ready(shared_state.values.pop_front())
         } else {
             // Set waker so that the thread can wake up the current task
             // when the timer has completed, ensuring that the future is polled
@@ -181,18 +234,26 @@ impl Future for AbiFuture {
 }
 
 pub struct AbiStream {
+    id: u64,
     shared_state: Arc<Mutex<AbiFutureState>>,
 }
 
+impl Drop for AbiStream {
+    fn drop(&mut self) {
+        Runtime::with(|runtime| runtime.cancel_if_pending(self.id))
+    }
+}
+
 impl Stream for AbiStream {
     type Item = Vec<u8>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         // Look at the shared state to see if the timer has already completed.
         let mut shared_state = self.shared_state.lock().unwrap();
-        if shared_state.completed {
-            shared_state.completed = false;
-            Poll::Ready(shared_state.value.take())
+        if let Some(item) = shared_state.values.pop_front() {
+            Poll::Ready(Some(item))
+        } else if shared_state.completed {
+            Poll::Ready(None)
         } else {
             // Set waker so that the thread can wake up the current task
             // when the timer has completed, ensuring that the future is polled
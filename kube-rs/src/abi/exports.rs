@@ -0,0 +1,89 @@
+//! Implementation of the exports the host calls, generated in the module by [`main`](crate::abi::main).
+//! Not meant to be called directly.
+
+use super::descriptor::DESCRIPTOR;
+use super::executor::Runtime;
+use std::ffi::c_void;
+use std::future::Future;
+use std::mem;
+
+pub fn run(main: impl Future<Output = ()> + 'static) {
+    Runtime::with(|runtime| {
+        runtime.spawn(main);
+        // Give a little push to the executor
+        runtime.run_until_stalled();
+    })
+}
+
+pub fn wakeup_future(future_id: u64, ptr: *const u8, len: usize) {
+    let value = if ptr.is_null() { None } else { Some(take_value(ptr, len)) };
+    Runtime::with(|runtime| {
+        runtime.complete_future(future_id, value);
+        runtime.run_until_stalled();
+    })
+}
+
+pub fn wakeup_stream(stream_id: u64, ptr: *const u8, len: usize) {
+    Runtime::with(|runtime| {
+        if ptr.is_null() {
+            runtime.close_stream(stream_id);
+        } else {
+            runtime.push_stream_items(stream_id, Some(take_value(ptr, len)));
+        }
+        runtime.run_until_stalled();
+    })
+}
+
+pub fn wakeup_stream_batch(stream_id: u64, ptr: *const u8, len: usize) {
+    let batch = take_value(ptr, len);
+    Runtime::with(|runtime| {
+        runtime.push_stream_batch(stream_id, &batch);
+        runtime.run_until_stalled();
+    })
+}
+
+pub fn wakeup_batch(ptr: *const u8, len: usize) {
+    let batch = take_value(ptr, len);
+    Runtime::with(|runtime| {
+        runtime.apply_batch(&batch);
+        // All the wakers are applied, so the executor runs once for the whole batch
+        runtime.run_until_stalled();
+    })
+}
+
+pub fn allocate(size: usize) -> *mut c_void {
+    let mut buffer = Vec::<u8>::with_capacity(size);
+    let pointer = buffer.as_mut_ptr();
+    // Say to compiler to forget about this memory cell
+    // Deallocation will be done by who's going to consume this allocation
+    mem::forget(buffer);
+
+    pointer as *mut c_void
+}
+
+/// Free an allocation made through `allocate` the host didn't hand over to the module
+pub fn deallocate(ptr: *mut c_void, size: usize) {
+    if !ptr.is_null() {
+        drop(unsafe { Vec::from_raw_parts(ptr as *mut u8, 0, size) });
+    }
+}
+
+/// Returns the location of the descriptor: the pointer in the high 32 bits, the length in the low 32 bits
+pub fn abi_descriptor() -> u64 {
+    ((DESCRIPTOR.as_ptr() as u64) << 32) | DESCRIPTOR.len() as u64
+}
+
+/// Take the ownership of the value the host allocated through `allocate`,
+/// so it's freed even when nobody is waiting for it anymore
+fn take_value(ptr: *const u8, len: usize) -> Vec<u8> {
+    if ptr.is_null() {
+        return Vec::new();
+    }
+    unsafe {
+        Vec::from_raw_parts(
+            ptr as *mut u8,
+            len as usize,
+            len as usize,
+        )
+    }
+}
@@ -1,74 +1,72 @@
-use serde::{Deserialize, Serialize};
-use super::memory;
-use std::ffi::c_void;
-use crate::abi::start_future;
+use std::time::Duration;
+use crate::abi::{start_future, start_stream};
+use futures::{Stream, StreamExt};
+use kube_abi_types::v1alpha1::{HttpError, HttpResponse, TimedHttpRequest};
 
 #[link(wasm_import_module = "http-proxy-abi")]
 extern "C" {
     fn request(ptr: *const u8, len: usize) -> u64;
+    // The first stream item is the response without the body, then the body chunks follow
+    fn request_stream(ptr: *const u8, len: usize) -> u64;
 }
 
-/// Data structure to serialize/deserialize http request
-#[derive(Serialize, Deserialize)]
-struct HttpRequest {
-    #[serde(with = "http_serde::method")]
-    method: http::Method,
+/// Execute the request through the host. If no timeout is provided, the host default one is used.
+pub async fn execute_request(req: http::Request<Vec<u8>>, timeout: Option<Duration>) -> Result<http::Response<Vec<u8>>, HttpError> {
+    let inner_request = TimedHttpRequest { request: req.into(), timeout };
+    let bytes = bincode::serialize(&inner_request).map_err(unencodable_request)?;
 
-    #[serde(with = "http_serde::uri")]
-    uri: http::Uri,
+    let async_request_id: u64 =
+        unsafe { request(bytes.as_ptr(), bytes.len()) }.into();
 
-    #[serde(with = "http_serde::header_map")]
-    headers: http::HeaderMap,
+    let response_raw = start_future(async_request_id).await.ok_or_else(missing_response)?;
 
-    body: Vec<u8>,
+    decode_response(&response_raw)
 }
 
-impl From<http::Request<Vec<u8>>> for HttpRequest {
-    fn from(req: http::Request<Vec<u8>>) -> Self {
-        let (parts, body) = req.into_parts();
+/// Execute the request through the host, streaming the response body.
+/// The timeout applies only to the response head, so it can be used for watches.
+pub async fn execute_request_stream(
+    req: http::Request<Vec<u8>>,
+    timeout: Option<Duration>,
+) -> Result<http::Response<impl Stream<Item=Result<Vec<u8>, HttpError>>>, HttpError> {
+    let inner_request = TimedHttpRequest { request: req.into(), timeout };
+    let bytes = bincode::serialize(&inner_request).map_err(unencodable_request)?;
 
-        HttpRequest {
-            method: parts.method,
-            uri: parts.uri,
-            headers: parts.headers,
-            body,
-        }
-    }
+    let async_request_id: u64 =
+        unsafe { request_stream(bytes.as_ptr(), bytes.len()) }.into();
+
+    let mut stream = start_stream(async_request_id);
+    let head_raw = stream
+        .next()
+        .await
+        .ok_or_else(|| HttpError::Transport("stream closed before the response head".to_string()))?;
+    let (parts, _) = decode_response(&head_raw)?.into_parts();
+
+    let body = stream.map(|chunk_raw| {
+        bincode::deserialize::<Result<Vec<u8>, HttpError>>(&chunk_raw)
+            .map_err(invalid_host_payload)
+            .and_then(|chunk| chunk)
+    });
+
+    Ok(http::Response::from_parts(parts, body))
 }
 
-#[derive(Serialize, Deserialize)]
-struct HttpResponse {
-    #[serde(with = "http_serde::status_code")]
-    status_code: http::StatusCode,
-
-    #[serde(with = "http_serde::header_map")]
-    headers: http::HeaderMap,
+/// Decode the response sent by the host, which may carry a transport failure
+pub(crate) fn decode_response(response_raw: &[u8]) -> Result<http::Response<Vec<u8>>, HttpError> {
+    let response_inner: Result<HttpResponse, HttpError> =
+        bincode::deserialize(response_raw).map_err(invalid_host_payload)?;
 
-    body: Vec<u8>,
+    response_inner.map(Into::into)
 }
 
-impl Into<http::Response<Vec<u8>>> for HttpResponse {
-    fn into(self) -> http::Response<Vec<u8>> {
-        let mut builder = http::response::Builder::new().status(self.status_code);
-
-        for (h, v) in self.headers.iter() {
-            builder = builder.header(h, v);
-        }
-
-        builder.body(self.body).unwrap()
-    }
+fn invalid_host_payload(err: bincode::Error) -> HttpError {
+    HttpError::Transport(format!("cannot decode the payload sent by the host: {}", err))
 }
 
-pub async fn execute_request(req: http::Request<Vec<u8>>) -> http::Response<Vec<u8>> {
-    let inner_request: HttpRequest = req.into();
-    let bytes = bincode::serialize(&inner_request).unwrap();
-
-    let async_request_id: u64 =
-        unsafe { request(bytes.as_ptr(), bytes.len()) }.into();
-
-    let response_raw = start_future(async_request_id).await.unwrap();
-
-    let response_inner: HttpResponse = bincode::deserialize(&response_raw).unwrap();
+pub(crate) fn unencodable_request(err: bincode::Error) -> HttpError {
+    HttpError::InvalidRequest(format!("cannot encode the request for the host: {}", err))
+}
 
-    response_inner.into()
-}
\ No newline at end of file
+pub(crate) fn missing_response() -> HttpError {
+    HttpError::Transport("the host completed the request without a response".to_string())
+}
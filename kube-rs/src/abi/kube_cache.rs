@@ -0,0 +1,21 @@
+use crate::Resource;
+use crate::abi::start_future;
+use super::http::{decode_response, missing_response, unencodable_request};
+use kube_abi_types::v1alpha1::{CacheRequest, CacheRequestVerb, HttpError};
+
+#[link(wasm_import_module = "kube-cache-abi")]
+extern "C" {
+    // Returns the future identifier
+    fn query(ptr: *const u8, len: usize) -> u64;
+}
+
+pub async fn query_cache(resource: &Resource, verb: CacheRequestVerb) -> Result<http::Response<Vec<u8>>, HttpError> {
+    let cache_request = CacheRequest { resource: resource.into(), verb };
+    let bytes = bincode::serialize(&cache_request).map_err(unencodable_request)?;
+
+    let async_request_id = unsafe { query(bytes.as_ptr(), bytes.len()) };
+
+    let response_raw = start_future(async_request_id).await.ok_or_else(missing_response)?;
+
+    decode_response(&response_raw)
+}
@@ -1,14 +1,7 @@
-use serde::{Deserialize, Serialize};
 use crate::Resource;
-use std::ffi::c_void;
+use crate::api::ListParams;
 use futures::Stream;
-use crate::api::params::WatchParams;
-
-#[derive(Serialize, Deserialize)]
-pub(crate) struct WatchRequest {
-    pub(crate) resource: Resource,
-    pub(crate) watch_params: WatchParams
-}
+use kube_abi_types::v1alpha1::WatchRequest;
 
 #[link(wasm_import_module = "kube-watch-abi")]
 extern "C" {
@@ -16,8 +9,12 @@ extern "C" {
     fn watch(watch_req_ptr: *const u8, watch_req_len: usize) -> u64;
 }
 
-pub fn register_watch(resource: Resource, watch_params: WatchParams) -> impl Stream<Item=Vec<u8>> {
-    let watch_request = WatchRequest{resource, watch_params };
+pub fn register_watch(resource: &Resource, list_params: &ListParams, resource_version: &str) -> impl Stream<Item=Vec<u8>> {
+    let watch_request = WatchRequest {
+        resource: resource.into(),
+        resource_version: resource_version.to_string(),
+        list_params: list_params.into(),
+    };
     let serialized_watch_request = bincode::serialize(&watch_request).unwrap();
 
     let watch_id = unsafe {
@@ -25,4 +22,4 @@ pub fn register_watch(resource: Resource, watch_params: WatchParams) -> impl Str
     };
 
     super::start_stream(watch_id)
-}
\ No newline at end of file
+}
@@ -1,12 +1,23 @@
 mod http;
 mod kube_watch;
-mod memory;
+mod kube_cache;
 mod executor;
+mod batch;
 mod delay;
+mod time;
+mod descriptor;
 
-pub use crate::abi::http::execute_request;
+pub use crate::abi::http::{execute_request, execute_request_stream};
 pub use kube_watch::register_watch;
-pub use delay::register_delay;
-pub use executor::get_mut_executor;
+pub use kube_cache::query_cache;
+pub use kube_abi_types::v1alpha1::{CacheRequestVerb, HttpError};
+pub use delay::{register_delay, HostTimer};
+pub use time::monotonic_now;
+pub use descriptor::host_supports;
+pub use executor::Runtime;
 pub use executor::start_stream;
-pub use executor::start_future;
\ No newline at end of file
+pub use executor::start_future;
+pub use kube_abi_macros::main;
+
+#[doc(hidden)]
+pub mod exports;
\ No newline at end of file
@@ -0,0 +1,20 @@
+use std::time::Duration;
+use super::host_supports;
+
+#[link(wasm_import_module = "time-abi")]
+extern "C" {
+    // Returns the monotonic time of the host in nanoseconds
+    fn now() -> u64;
+}
+
+/// Monotonic time of the host, elapsed since an arbitrary origin.
+/// When the host runs in virtual time, it advances only when the delays of the module fire.
+///
+/// Returns `None` if the host doesn't provide the `time-abi`.
+pub fn monotonic_now() -> Option<Duration> {
+    if host_supports("time-abi.now") {
+        Some(Duration::from_nanos(unsafe { now() }))
+    } else {
+        None
+    }
+}
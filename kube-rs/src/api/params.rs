@@ -1,6 +1,6 @@
 ///! A port of *Optionals from apimachinery/types.go
 use crate::{Error, Result};
-use serde::{Serialize, Deserialize};
+use serde::Serialize;
 
 /// Common query parameters used in watch/list/delete calls on collections
 #[derive(Default, Clone)]
@@ -47,6 +47,19 @@ pub struct ListParams {
     pub continue_token: Option<String>,
 }
 
+impl From<&ListParams> for kube_abi_types::v1alpha1::ListParams {
+    fn from(lp: &ListParams) -> Self {
+        kube_abi_types::v1alpha1::ListParams {
+            label_selector: lp.label_selector.clone(),
+            field_selector: lp.field_selector.clone(),
+            timeout: lp.timeout,
+            allow_bookmarks: lp.allow_bookmarks,
+            limit: lp.limit,
+            continue_token: lp.continue_token.clone(),
+        }
+    }
+}
+
 impl ListParams {
     pub(crate) fn validate(&self) -> Result<()> {
         if let Some(to) = &self.timeout {
@@ -118,16 +131,6 @@ impl ListParams {
     }
 }
 
-#[derive(Default, Clone, Serialize, Deserialize)]
-#[allow(missing_docs)]
-pub struct WatchParams {
-    pub resource_version: String,
-    pub field_selector: Option<String>,
-    pub include_uninitialized: bool,
-    pub label_selector: Option<String>,
-    pub allow_bookmarks: bool,
-}
-
 /// Common query parameters for put/post calls
 #[derive(Default, Clone)]
 pub struct PostParams {
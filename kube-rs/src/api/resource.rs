@@ -31,6 +31,18 @@ pub struct Resource {
     pub namespace: Option<String>,
 }
 
+impl From<&Resource> for kube_abi_types::v1alpha1::Resource {
+    fn from(resource: &Resource) -> Self {
+        kube_abi_types::v1alpha1::Resource {
+            api_version: resource.api_version.clone(),
+            group: resource.group.clone(),
+            kind: resource.kind.clone(),
+            version: resource.version.clone(),
+            namespace: resource.namespace.clone(),
+        }
+    }
+}
+
 impl Resource {
     /// Cluster level resources, or resources viewed across all namespaces
     pub fn all<K: k8s_openapi::Resource>() -> Self {
@@ -205,4 +205,10 @@ where
         let req = self.resource.logs(name, lp)?;
         Ok(self.client.request_text(req).await?)
     }
+
+    /// Fetch logs as a stream of bytes
+    pub async fn log_stream(&self, name: &str, lp: &LogParams) -> Result<impl Stream<Item = Result<Bytes>>> {
+        let req = self.resource.logs(name, lp)?;
+        Ok(self.client.request_text_stream(req).await?)
+    }
 }
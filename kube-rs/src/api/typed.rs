@@ -7,12 +7,11 @@ use crate::{
         DeleteParams, ListParams, Meta, ObjectList, PatchParams, PostParams, Resource,
     },
     client::{Client, Status},
-    Result,
+    Error, Result,
     abi
 };
 use crate::api::WatchEvent;
 use futures::{Stream, StreamExt};
-use crate::api::params::WatchParams;
 
 /// An easy Api interaction helper
 ///
@@ -115,6 +114,42 @@ where
         self.client.request::<ObjectList<K>>(req).await
     }
 
+    /// Get a named resource from the cache shared by the host
+    ///
+    /// The host keeps a single cache for each resource, filled by a list and a watch,
+    /// so this doesn't perform a request to the API server.
+    /// The returned object may be slightly behind the one returned by [`Api::get`].
+    pub async fn get_cached(&self, name: &str) -> Result<K> {
+        let verb = abi::CacheRequestVerb::Get { name: name.to_string() };
+        self.client.request_cached::<K>(&self.resource, verb).await
+    }
+
+    /// Get a list of resources from the cache shared by the host
+    ///
+    /// Only the label and field selectors of the `ListParams` are used:
+    ///
+    /// ```no_run
+    /// use kube::{api::{Api, ListParams, Meta}, Client};
+    /// use k8s_openapi::api::core::v1::Pod;
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), kube::Error> {
+    ///     let client = Client::try_default().await?;
+    ///     let pods: Api<Pod> = Api::namespaced(client, "apps");
+    ///     let lp = ListParams::default().labels("app=blog");
+    ///     for p in pods.list_cached(&lp).await? {
+    ///         println!("Found Pod: {}", Meta::name(&p));
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn list_cached(&self, lp: &ListParams) -> Result<ObjectList<K>> {
+        let verb = abi::CacheRequestVerb::List {
+            label_selector: lp.label_selector.clone(),
+            field_selector: lp.field_selector.clone(),
+        };
+        self.client.request_cached::<ObjectList<K>>(&self.resource, verb).await
+    }
+
     /// Create a resource
     ///
     /// This function requires a type that Serializes to `K`, which can be:
@@ -334,19 +369,12 @@ where
         lp: &ListParams,
         version: &str,
     ) -> Result<impl Stream<Item = Result<WatchEvent<K>>>> {
-        //TODO(slinkydeveloper) this conversion is made to avoid changing the signature of this method
-
-        let watch_params = WatchParams {
-            resource_version: String::from(version),
-            field_selector: lp.field_selector.clone(),
-            include_uninitialized: false,
-            label_selector: lp.label_selector.clone(),
-            allow_bookmarks: lp.allow_bookmarks
-        };
+        // Validate the params like the request would do
+        self.resource.watch(lp, version)?;
 
         Ok(
-            abi::register_watch(self.resource.clone(), watch_params)
-                .map(|vec| Ok(serde_json::from_slice(&vec).unwrap()))
+            abi::register_watch(&self.resource, lp, version)
+                .map(|vec| serde_json::from_slice(&vec).map_err(Error::SerdeError))
         )
     }
 }
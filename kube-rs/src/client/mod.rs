@@ -6,13 +6,16 @@
 //! the [`Api`][crate::api::Api] type for more structured
 //! interaction with the kuberneres API
 
-use crate::{error::ErrorResponse, Error, Result, abi};
+use crate::{error::ErrorResponse, Error, Resource, Result, abi};
 
+use bytes::Bytes;
 use either::{Either, Left, Right};
+use futures::{Stream, StreamExt, TryStreamExt};
 use http::{self, Request, StatusCode};
 use k8s_openapi::apimachinery::pkg::apis::meta::v1 as k8s_meta_v1;
 use serde::{de::DeserializeOwned, Deserialize};
 use serde_json::{self, Value};
+use std::time::Duration;
 
 /// Client for connecting with a Kubernetes cluster.
 ///
@@ -23,6 +26,7 @@ use serde_json::{self, Value};
 #[derive(Clone)]
 pub struct Client {
     default_ns: String,
+    timeout: Option<Duration>,
 }
 
 impl Client {
@@ -37,7 +41,16 @@ impl Client {
     /// use `Config::try_from` (note that this requires [`std::convert::TryFrom`]
     /// to be in scope.)
     pub fn new(default_ns: String) -> Self {
-        Client { default_ns }
+        Client { default_ns, timeout: None }
+    }
+
+    /// Set the timeout of the requests sent by this [`Client`], replacing the host default one.
+    ///
+    /// When a request times out, it fails with [`Error::Transport`] wrapping [`abi::HttpError::Timeout`].
+    /// For watches and log streams, the timeout applies only until the response head is received.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
     }
 
     /// Create and initialize a [`Client`] using the inferred
@@ -55,7 +68,7 @@ impl Client {
     }
 
     async fn send(&self, request: http::Request<Vec<u8>>) -> Result<http::Response<Vec<u8>>> {
-        Ok(abi::execute_request(request).await)
+        Ok(abi::execute_request(request, self.timeout).await?)
     }
 
     /// Perform a raw HTTP request against the API and deserialize the response
@@ -76,13 +89,40 @@ impl Client {
     /// as a string
     pub async fn request_text(&self, request: http::Request<Vec<u8>>) -> Result<String> {
         let res: http::Response<Vec<u8>> = self.send(request).await?;
+        response_text(res)
+    }
+
+    /// Perform a raw HTTP request against the API and get back the response
+    /// as a stream of bytes
+    pub async fn request_text_stream(
+        &self,
+        request: http::Request<Vec<u8>>,
+    ) -> Result<impl Stream<Item = Result<Bytes>>> {
+        let res = abi::execute_request_stream(request, self.timeout).await?;
         trace!("Status = {:?}", res.status());
         let s = res.status();
         let (_, body) = res.into_parts();
-        let text = String::from_utf8(body)?;
-        handle_api_errors(&text, s)?;
+        if s.is_client_error() || s.is_server_error() {
+            let text = String::from_utf8(body.try_concat().await?)?;
+            return Err(api_error(&text, s));
+        }
 
-        Ok(text)
+        Ok(body.map(|chunk| Ok(Bytes::from(chunk?))))
+    }
+
+    /// Run a query against the cache shared by the host and deserialize the response
+    /// as JSON to some known type.
+    pub async fn request_cached<T>(&self, resource: &Resource, verb: abi::CacheRequestVerb) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let res = abi::query_cache(resource, verb).await?;
+        let text = response_text(res)?;
+
+        serde_json::from_str(&text).map_err(|e| {
+            warn!("{}, {:?}", text, e);
+            Error::SerdeError(e)
+        })
     }
 
     /// Perform a raw HTTP request against the API and get back either an object
@@ -160,6 +200,17 @@ impl Client {
     }
 }
 
+/// Get the body of the response as a string, or the error returned by kube
+fn response_text(res: http::Response<Vec<u8>>) -> Result<String> {
+    trace!("Status = {:?}", res.status());
+    let s = res.status();
+    let (_, body) = res.into_parts();
+    let text = String::from_utf8(body)?;
+    handle_api_errors(&text, s)?;
+
+    Ok(text)
+}
+
 /// Kubernetes returned error handling
 ///
 /// Either kube returned an explicit ApiError struct,
@@ -169,32 +220,37 @@ impl Client {
 /// The latter is probably a bug if encountered.
 fn handle_api_errors(text: &str, s: StatusCode) -> Result<()> {
     if s.is_client_error() || s.is_server_error() {
-        // Print better debug when things do fail
-        // trace!("Parsing error: {}", text);
-        if let Ok(errdata) = serde_json::from_str::<ErrorResponse>(text) {
-            debug!("Unsuccessful: {:?}", errdata);
-            Err(Error::Api(errdata))
-        } else {
-            warn!("Unsuccessful data error parse: {}", text);
-            // Propagate errors properly via reqwest
-            let ae = ErrorResponse {
-                status: s.to_string(),
-                code: s.as_u16(),
-                message: format!("{:?}", text),
-                reason: "Failed to parse error data".into(),
-            };
-            debug!("Unsuccessful: {:?} (reconstruct)", ae);
-            Err(Error::Api(ae))
-        }
+        Err(api_error(text, s))
     } else {
         Ok(())
     }
 }
 
+fn api_error(text: &str, s: StatusCode) -> Error {
+    // Print better debug when things do fail
+    // trace!("Parsing error: {}", text);
+    if let Ok(errdata) = serde_json::from_str::<ErrorResponse>(text) {
+        debug!("Unsuccessful: {:?}", errdata);
+        Error::Api(errdata)
+    } else {
+        warn!("Unsuccessful data error parse: {}", text);
+        // Propagate errors properly via reqwest
+        let ae = ErrorResponse {
+            status: s.to_string(),
+            code: s.as_u16(),
+            message: format!("{:?}", text),
+            reason: "Failed to parse error data".into(),
+        };
+        debug!("Unsuccessful: {:?} (reconstruct)", ae);
+        Error::Api(ae)
+    }
+}
+
 impl Default for Client {
     fn default() -> Self {
         Client {
             default_ns: "default".to_string(),
+            timeout: None,
         }
     }
 }
@@ -41,6 +41,10 @@ pub enum Error {
     #[error("Error executing request")]
     RequestSend,
 
+    /// The host failed to send the request or to receive the response
+    #[error("TransportError: {0}")]
+    Transport(#[from] kube_abi_types::v1alpha1::HttpError),
+
     /// Error parsing a response
     #[error("Error parsing response")]
     RequestParse,
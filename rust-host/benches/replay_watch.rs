@@ -0,0 +1,143 @@
+//! Replays a watch stream, comparing how the events are encoded and copied in the module memory:
+//! byte by byte, with a bulk copy per event, and in batches framed for `wakeup_stream_batch` and `wakeup_batch`.
+//!
+//! Only the host side of the wakeups is measured: the memory is not attached to a module instance,
+//! so the calls into the module and the decoding done by the guest are not part of the timings.
+//!
+//! The default fixture is synthetic: pod events generated to look like the initial list of a watch.
+//! Run it with `cargo bench --bench replay_watch`. To replay a recording of a real cluster, set `WATCH_RECORDING`
+//! to a file with one watch event per line, as returned by `kubectl get --raw '/api/v1/pods?watch=1'`.
+
+#[path = "../src/abi/memory.rs"]
+#[allow(dead_code)]
+mod memory;
+
+use std::time::{Duration, Instant};
+use wasmer_runtime::Memory;
+use wasmer_runtime_core::types::MemoryDescriptor;
+use wasmer_runtime_core::units::Pages;
+
+const DEFAULT_RECORDING: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/fixtures/pods-watch.jsonl");
+const REPLAYS: usize = 200;
+const BATCH_SIZE: usize = 64;
+
+fn main() {
+    let recording_path = std::env::var("WATCH_RECORDING").unwrap_or_else(|_| DEFAULT_RECORDING.to_string());
+    let recording = std::fs::read(&recording_path).expect("Cannot read the watch recording");
+    let events: Vec<Vec<u8>> = recording
+        .split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_vec())
+        .collect();
+    let total_bytes: usize = events.iter().map(Vec::len).sum();
+    println!(
+        "Replaying {} events ({} bytes) from {} {} times",
+        events.len(),
+        total_bytes,
+        &recording_path,
+        REPLAYS
+    );
+
+    let memory = Memory::new(MemoryDescriptor::new(Pages(256), None, false).unwrap()).unwrap();
+
+    let entries: Vec<memory::BatchEntry> = events
+        .iter()
+        .map(|event| memory::BatchEntry {
+            async_request_id: 1,
+            stream: true,
+            value: Some(event.clone()),
+        })
+        .collect();
+
+    report("byte by byte", &events, replay(&memory, |a| copy_byte_by_byte(&memory, a, &events)));
+    report("bulk copy per event", &events, replay(&memory, |a| copy_bulk(&memory, a, &events)));
+    report("stream batches", &events, replay(&memory, |a| copy_batches(&memory, a, &events)));
+    report("wakeup batches", &events, replay(&memory, |a| copy_wakeup_batches(&memory, a, &entries)));
+}
+
+/// Replay the events with the copy strategy, returning the time it took
+fn replay(memory: &Memory, copy: impl Fn(&mut Allocator)) -> Duration {
+    let mut allocator = Allocator::new(memory);
+    // Warm up
+    copy(&mut allocator);
+
+    let start = Instant::now();
+    for _ in 0..REPLAYS {
+        copy(&mut allocator);
+    }
+    start.elapsed()
+}
+
+fn report(strategy: &str, events: &[Vec<u8>], elapsed: Duration) {
+    let events_count = (events.len() * REPLAYS) as f64;
+    let bytes = (events.iter().map(Vec::len).sum::<usize>() * REPLAYS) as f64;
+    println!(
+        "{:<22} {:>10.1} ns/event {:>10.1} MiB/s",
+        strategy,
+        elapsed.as_nanos() as f64 / events_count,
+        bytes / (1024.0 * 1024.0) / elapsed.as_secs_f64()
+    );
+}
+
+/// The copy done by the host before the bulk copy, one wakeup per event
+fn copy_byte_by_byte(memory: &Memory, allocator: &mut Allocator, events: &[Vec<u8>]) {
+    for event in events {
+        let ptr = allocator.allocate(event.len()) as usize;
+        let view = memory.view::<u8>();
+        for (i, b) in event.iter().enumerate() {
+            view[ptr + i].set(*b);
+        }
+    }
+}
+
+/// One wakeup per event
+fn copy_bulk(memory: &Memory, allocator: &mut Allocator, events: &[Vec<u8>]) {
+    for event in events {
+        let ptr = allocator.allocate(event.len());
+        assert!(memory::write_bytes(memory, ptr, event));
+    }
+}
+
+/// One wakeup per batch of items of the same stream, like the host does for modules exporting `wakeup_stream_batch`
+fn copy_batches(memory: &Memory, allocator: &mut Allocator, events: &[Vec<u8>]) {
+    for batch in events.chunks(BATCH_SIZE) {
+        let batch = memory::encode_batch(batch);
+        let ptr = allocator.allocate(batch.len());
+        assert!(memory::write_bytes(memory, ptr, &batch));
+    }
+}
+
+/// One wakeup per batch of async results, like the host does for modules exporting `wakeup_batch`
+fn copy_wakeup_batches(memory: &Memory, allocator: &mut Allocator, entries: &[memory::BatchEntry]) {
+    for batch in entries.chunks(BATCH_SIZE) {
+        let batch = memory::encode_wakeup_batch(batch);
+        let ptr = allocator.allocate(batch.len());
+        assert!(memory::write_bytes(memory, ptr, &batch));
+    }
+}
+
+/// Bump allocator standing in for the module `allocate`, wrapping around at the end of the memory
+struct Allocator {
+    next: u32,
+    size: u32,
+}
+
+impl Allocator {
+    fn new(memory: &Memory) -> Allocator {
+        Allocator {
+            next: 0,
+            size: memory.size().bytes().0 as u32,
+        }
+    }
+
+    fn allocate(&mut self, len: usize) -> u32 {
+        let len = len as u32;
+        assert!(len <= self.size, "the event doesn't fit the memory");
+        if self.next + len > self.size {
+            self.next = 0;
+        }
+        let ptr = self.next;
+        self.next += len;
+        ptr
+    }
+}
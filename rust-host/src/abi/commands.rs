@@ -8,3 +8,22 @@ pub struct AbiCommand<T: Sized + Debug> {
     pub controller_name: String,
     pub value: T,
 }
+
+/// Command sent to the executors (http, delay, watch)
+#[derive(Debug, Clone)]
+pub enum ExecutorCommand<T: Sized + Debug> {
+    /// Start a new async request
+    Start(AbiCommand<T>),
+    /// Complete the async request with a 403, because the module policy doesn't allow it
+    Deny { command: AbiCommand<T>, message: String },
+    /// Cancel a single async request, because the module is not waiting for it anymore
+    Cancel { controller_name: String, async_request_id: u64 },
+    /// Cancel all the outstanding async requests of a controller
+    CancelAll { controller_name: String },
+}
+
+impl<T: Sized + Debug> From<AbiCommand<T>> for ExecutorCommand<T> {
+    fn from(command: AbiCommand<T>) -> Self {
+        ExecutorCommand::Start(command)
+    }
+}
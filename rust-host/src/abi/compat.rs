@@ -0,0 +1,228 @@
+use super::{Abi, AbiError, AbiVersion};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use wasmer_runtime::{ImportObject, Module};
+use wasmer_runtime_core::import::Namespace;
+use wasmer_runtime_core::typed_func::DynamicFunc;
+use wasmer_runtime_core::types::{ExternDescriptor, Type, Value};
+
+/// Descriptor exported by the modules through `abi_descriptor`,
+/// describing the abi the module was built against.
+///
+/// Imports are named `<namespace>.<name>`, eg `http-proxy-abi.request`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AbiDescriptor {
+    /// Name of the abi, eg `rust_v1alpha1`
+    pub abi: String,
+    /// Imports the module uses
+    #[serde(default)]
+    pub imports: Vec<String>,
+    /// Imports the module can live without, checking them through `abi-info.supports` before using them
+    #[serde(default)]
+    pub optional_imports: Vec<String>,
+    /// Abi features the module requires
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+impl AbiDescriptor {
+    /// Check the module can run with the abi configured in its manifest,
+    /// given the imports the host stubbed because it doesn't know them
+    pub fn validate(&self, abi_version: &AbiVersion, abi: &dyn Abi, stubbed_imports: &[String]) -> Result<(), AbiError> {
+        let mut problems = Vec::new();
+
+        if self.abi != abi_version.name() {
+            problems.push(format!(
+                "the module was built for the abi '{}', while the manifest declares '{}'",
+                &self.abi,
+                abi_version.name()
+            ));
+        }
+
+        let missing_imports: Vec<&String> = self
+            .imports
+            .iter()
+            .chain(stubbed_imports.iter())
+            .filter(|import| !self.optional_imports.contains(import))
+            .filter(|import| !provides_import(abi, import))
+            .collect();
+        if !missing_imports.is_empty() {
+            problems.push(format!("imports not provided by the host: {}", join(missing_imports)));
+        }
+
+        let unsupported_features: Vec<&String> = self
+            .features
+            .iter()
+            .filter(|feature| !abi.supported_features().contains(&feature.as_str()))
+            .collect();
+        if !unsupported_features.is_empty() {
+            problems.push(format!("features not supported by the host: {}", join(unsupported_features)));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(AbiError::Incompatible(problems.join("; ")))
+        }
+    }
+}
+
+fn provides_import(abi: &dyn Abi, import: &str) -> bool {
+    match import.find('.') {
+        Some(i) => abi.provides_import(&import[..i], &import[i + 1..]),
+        None => false,
+    }
+}
+
+fn join(values: Vec<&String>) -> String {
+    values.iter().map(|v| format!("'{}'", v)).collect::<Vec<String>>().join(", ")
+}
+
+/// Satisfy the function imports of the module the host doesn't provide with stubs returning zeros,
+/// so the module can be instantiated and its descriptor can tell if it can live without them.
+/// Returns the stubbed imports.
+pub fn stub_unknown_imports(module: &Module, abi: &dyn Abi, imports: &mut ImportObject) -> Vec<String> {
+    let mut stubbed_imports = Vec::new();
+
+    for import in module.imports() {
+        if import.namespace.starts_with("wasi") || abi.provides_import(&import.namespace, &import.name) {
+            continue;
+        }
+        let signature = match import.ty {
+            ExternDescriptor::Function(signature) => signature,
+            // Memories, tables and globals are not part of the abi, let the instantiation fail
+            _ => continue,
+        };
+
+        let import_name = format!("{}.{}", &import.namespace, &import.name);
+        debug!("Stubbing the unknown import '{}'", &import_name);
+
+        let returns: Vec<Value> = signature
+            .returns()
+            .iter()
+            .map(|ty| match ty {
+                Type::I32 => Value::I32(0),
+                Type::I64 => Value::I64(0),
+                Type::F32 => Value::F32(0.0),
+                Type::F64 => Value::F64(0.0),
+                Type::V128 => Value::V128(0),
+            })
+            .collect();
+        let stub_name = import_name.clone();
+        let stub = DynamicFunc::new(Arc::new(signature), move |_, _| {
+            warn!("Called the stub of the unsupported import '{}'", &stub_name);
+            returns.clone()
+        });
+
+        // Extending the imports merges the stub into the namespace, if the host already provides it
+        let mut namespace = Namespace::new();
+        namespace.insert(import.name.clone(), stub);
+        let mut stub_imports = ImportObject::new();
+        stub_imports.register(import.namespace.clone(), namespace);
+        imports.extend(stub_imports);
+
+        stubbed_imports.push(import_name);
+    }
+
+    stubbed_imports
+}
+
+#[cfg(all(test, feature = "abi-rust-v1alpha1"))]
+mod tests {
+    use super::*;
+    use crate::modules::ModuleLimits;
+    use wasmer_runtime::Func;
+
+    /// Module using an import a newer host would provide
+    const MODULE_WITH_UNKNOWN_IMPORT: &str = r#"
+        (module
+          (import "future-abi" "frobnicate" (func $frobnicate (param i32) (result i64)))
+          (func (export "call_frobnicate") (result i64)
+            (call $frobnicate (i32.const 42))))
+    "#;
+
+    fn descriptor(abi: &str, imports: &[&str], optional_imports: &[&str], features: &[&str]) -> AbiDescriptor {
+        let to_strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+        AbiDescriptor {
+            abi: abi.to_string(),
+            imports: to_strings(imports),
+            optional_imports: to_strings(optional_imports),
+            features: to_strings(features),
+        }
+    }
+
+    fn incompatibility(result: Result<(), AbiError>) -> String {
+        match result {
+            Err(AbiError::Incompatible(message)) => message,
+            other => panic!("expected an incompatible module, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn compatible_descriptor() {
+        let version = AbiVersion::RustV1Alpha1;
+        let abi = version.get_abi(ModuleLimits::default());
+        let descriptor = descriptor(
+            "rust_v1alpha1",
+            &["http-proxy-abi.request", "kube-watch-abi.watch"],
+            &["kube-cache-abi.query"],
+            &["http-errors"],
+        );
+        assert!(descriptor.validate(&version, abi.as_ref(), &[]).is_ok());
+    }
+
+    #[test]
+    fn abi_name_mismatch() {
+        let version = AbiVersion::RustV1Alpha1;
+        let abi = version.get_abi(ModuleLimits::default());
+        let message = incompatibility(descriptor("json_v1alpha1", &[], &[], &[]).validate(&version, abi.as_ref(), &[]));
+        assert!(message.contains("built for the abi 'json_v1alpha1'"), "{}", message);
+    }
+
+    #[test]
+    fn required_import_not_provided() {
+        let version = AbiVersion::RustV1Alpha1;
+        let abi = version.get_abi(ModuleLimits::default());
+        let required = descriptor("rust_v1alpha1", &["http-proxy-abi.request", "future-abi.frobnicate"], &[], &[]);
+        let message = incompatibility(required.validate(&version, abi.as_ref(), &[]));
+        assert!(message.contains("imports not provided by the host: 'future-abi.frobnicate'"), "{}", message);
+
+        // Stubbed imports are required too, unless the descriptor lists them as optional
+        let stubbed = vec!["future-abi.frobnicate".to_string()];
+        let not_listed = descriptor("rust_v1alpha1", &[], &[], &[]);
+        let message = incompatibility(not_listed.validate(&version, abi.as_ref(), &stubbed));
+        assert!(message.contains("'future-abi.frobnicate'"), "{}", message);
+    }
+
+    #[test]
+    fn unknown_feature() {
+        let version = AbiVersion::RustV1Alpha1;
+        let abi = version.get_abi(ModuleLimits::default());
+        let descriptor = descriptor("rust_v1alpha1", &[], &[], &["http-errors", "time-travel"]);
+        let message = incompatibility(descriptor.validate(&version, abi.as_ref(), &[]));
+        assert_eq!(message, "features not supported by the host: 'time-travel'");
+    }
+
+    #[test]
+    fn optional_imports_are_stubbed() {
+        let version = AbiVersion::RustV1Alpha1;
+        let abi = version.get_abi(ModuleLimits::default());
+        let wasm = wabt::wat2wasm(MODULE_WITH_UNKNOWN_IMPORT).unwrap();
+        let module = ModuleLimits::default().compile(&wasm).unwrap();
+
+        // Without the stub the instantiation fails
+        assert!(module.instantiate(&ImportObject::new()).is_err());
+
+        let mut imports = ImportObject::new();
+        let stubbed = stub_unknown_imports(&module, abi.as_ref(), &mut imports);
+        assert_eq!(stubbed, vec!["future-abi.frobnicate".to_string()]);
+
+        let instance = module.instantiate(&imports).unwrap();
+        let call: Func<(), i64> = instance.exports.get("call_frobnicate").unwrap();
+        assert_eq!(call.call().unwrap(), 0);
+
+        let descriptor = descriptor("rust_v1alpha1", &[], &["future-abi.frobnicate"], &[]);
+        assert!(descriptor.validate(&version, abi.as_ref(), &stubbed).is_ok());
+    }
+}
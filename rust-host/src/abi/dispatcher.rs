@@ -1,6 +1,9 @@
-use crate::modules::ControllerModule;
+use crate::modules::{ModuleActor, ModuleMessage};
+use crate::abi::{AbiConfig, AbiError};
+use kube_abi_types::v1alpha1::HttpError;
 use std::collections::HashMap;
-use tokio::sync::mpsc::Receiver;
+use std::sync::atomic::Ordering;
+use tokio::sync::mpsc::{Receiver, UnboundedReceiver};
 
 #[derive(PartialEq, Eq, Hash, Debug)]
 pub enum AsyncType {
@@ -8,33 +11,141 @@ pub enum AsyncType {
     Stream,
 }
 
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(Debug)]
 pub struct AsyncResult {
     pub controller_name: String,
     pub async_request_id: u64,
     pub async_type: AsyncType,
-    pub value: Option<Vec<u8>>,
+    pub value: Option<AsyncValue>,
+}
+
+/// Value produced by an executor, encoded by the abi of the module before waking it up
+#[derive(Debug)]
+pub enum AsyncValue {
+    /// Response of the API server or of the shared cache. For streamed responses, the body is empty
+    HttpResponse(Result<http::Response<Vec<u8>>, HttpError>),
+    /// Chunk of a streamed response body
+    HttpBodyChunk(Result<Vec<u8>, HttpError>),
+    /// Watch event, as received from the API server
+    WatchEvent(Vec<u8>),
+}
+
+/// Lifecycle events of the controller modules
+pub enum ModuleEvent {
+    /// A new module was compiled and should replace the module with the same name, if any
+    Loaded(ModuleActor),
+    /// The module with the provided name was removed
+    Removed(String),
+    /// The module instance started with `first_async_request_id` failed
+    Failed {
+        controller_name: String,
+        first_async_request_id: u64,
+        error: AbiError,
+    },
+}
+
+/// A started module, together with the first async request id it generated.
+/// Results with a lower id were requested by a previous instance of the module
+struct RunningModule {
+    actor: ModuleActor,
+    first_async_request_id: u64,
+    /// A module failed with an error is quarantined: it's not woken up anymore,
+    /// until it's replaced by a new version
+    quarantined: Option<AbiError>,
 }
 
-pub struct AsyncResultDispatcher {}
+/// Routes the async results to the executors of the modules
+pub struct AsyncResultDispatcher {
+    modules: HashMap<String, RunningModule>,
+    abi_config: AbiConfig,
+}
 
 impl AsyncResultDispatcher {
-    pub async fn start(map: HashMap<String, ControllerModule>, mut rx: Receiver<AsyncResult>) -> anyhow::Result<()> {
-        let mut map = map;
+    pub async fn start(
+        mut modules_rx: UnboundedReceiver<ModuleEvent>,
+        mut rx: Receiver<AsyncResult>,
+        abi_config: AbiConfig,
+    ) -> anyhow::Result<()> {
+        let mut dispatcher = AsyncResultDispatcher {
+            modules: HashMap::new(),
+            abi_config,
+        };
 
         info!("Starting the watch events listener loop");
 
-        while let Some(async_result) = rx.recv().await {
-            if let Some(controller) = map.remove(&async_result.controller_name) {
-                controller.wakeup(async_result.async_request_id, async_result.async_type, async_result.value)?;
-                map.insert(async_result.controller_name.clone(), controller);
-            } else {
-                return Err(anyhow::anyhow!(
-                    "Cannot find controller for event {:?}",
-                    async_result
-                ));
+        loop {
+            tokio::select! {
+                Some(module_event) = modules_rx.recv() => match module_event {
+                    ModuleEvent::Loaded(actor) => dispatcher.swap_module(actor).await,
+                    ModuleEvent::Removed(controller_name) => dispatcher.remove_module(&controller_name),
+                    ModuleEvent::Failed { controller_name, first_async_request_id, error } =>
+                        dispatcher.quarantine_module(&controller_name, first_async_request_id, error),
+                },
+                Some(async_result) = rx.recv() => dispatcher.dispatch(async_result).await,
+                else => break,
             }
         }
         Ok(())
     }
+
+    async fn swap_module(&mut self, mut actor: ModuleActor) {
+        let controller_name = actor.name().to_string();
+        self.remove_module(&controller_name);
+
+        // The old instance is gone, so every id generated from now on belongs to the new one
+        let first_async_request_id = self.abi_config.async_request_counter.load(Ordering::SeqCst);
+
+        info!("Starting controller '{}'", &controller_name);
+        if let Err(e) = actor.send(ModuleMessage::Start { first_async_request_id }).await {
+            error!("Cannot start controller '{}': {}", &controller_name, e);
+            return;
+        }
+
+        self.modules.insert(controller_name, RunningModule {
+            actor,
+            first_async_request_id,
+            quarantined: None,
+        });
+    }
+
+    fn quarantine_module(&mut self, controller_name: &str, first_async_request_id: u64, error: AbiError) {
+        match self.modules.get_mut(controller_name) {
+            Some(running) if running.first_async_request_id == first_async_request_id => {
+                error!("Controller '{}' failed and is now quarantined: {}", controller_name, &error);
+                running.quarantined = Some(error);
+                self.abi_config.cancel_all(controller_name);
+            }
+            _ => debug!("Controller '{}' failed after it was replaced: {}", controller_name, &error),
+        }
+    }
+
+    fn remove_module(&mut self, controller_name: &str) {
+        // Dropping the actor closes its mailbox and stops its thread
+        if self.modules.remove(controller_name).is_some() {
+            info!("Stopping controller '{}'", controller_name);
+            self.abi_config.cancel_all(controller_name);
+        }
+    }
+
+    async fn dispatch(&mut self, async_result: AsyncResult) {
+        match self.modules.get_mut(&async_result.controller_name) {
+            Some(RunningModule { quarantined: Some(error), .. }) => {
+                debug!(
+                    "Skipping result ({}, {}) for quarantined controller, failed with: {}",
+                    &async_result.controller_name, &async_result.async_request_id, error
+                );
+            }
+            Some(running) if async_result.async_request_id >= running.first_async_request_id => {
+                if let Err(e) = running.actor.send(ModuleMessage::Wakeup(async_result)).await {
+                    warn!("{}", e);
+                }
+            }
+            _ => {
+                debug!(
+                    "Dropping result for a controller not running anymore ({}, {})",
+                    &async_result.controller_name, &async_result.async_request_id
+                );
+            }
+        }
+    }
 }
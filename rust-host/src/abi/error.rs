@@ -0,0 +1,65 @@
+use thiserror::Error;
+use wasmer_runtime::error::RuntimeError;
+
+/// Errors while interacting with a module through its abi
+#[derive(Error, Debug)]
+pub enum AbiError {
+    /// The module trapped while executing one of its exports
+    #[error("Trap while executing '{function}': {message}")]
+    Trap { function: String, message: String },
+
+    /// The module went over its limits
+    #[error("Limit exceeded: {0}")]
+    LimitExceeded(String),
+
+    /// The module sent to the host a payload that cannot be decoded
+    #[error("Bad payload: {0}")]
+    BadPayload(String),
+
+    /// The module doesn't export a function required by the abi
+    #[error("Missing export '{0}'")]
+    MissingExport(String),
+
+    /// The host cannot allocate memory in the module to write a payload
+    #[error("Cannot allocate {size} bytes: {message}")]
+    Allocation { size: u32, message: String },
+
+    /// The module cannot be instantiated
+    #[error("Cannot instantiate the module: {0}")]
+    Instantiation(String),
+
+    /// The module was built against an abi the host cannot provide
+    #[error("Incompatible module: {0}")]
+    Incompatible(String),
+
+    /// The host cannot encode the payload to send to the module
+    #[error("Cannot encode the payload: {0}")]
+    Encoding(String),
+
+    /// The executor serving the async requests of the module is not running anymore
+    #[error("Executor stopped: {0}")]
+    ExecutorStopped(String),
+}
+
+impl AbiError {
+    /// Convert the error returned by a call to a module export.
+    /// If the trap was triggered by a host function, the original error is returned.
+    pub fn from_runtime_error(function: &str, error: RuntimeError) -> AbiError {
+        match error {
+            RuntimeError::User(payload) => match payload.downcast::<AbiError>() {
+                Ok(abi_error) => *abi_error,
+                Err(_) => AbiError::Trap {
+                    function: function.to_string(),
+                    message: "host function failure".to_string(),
+                },
+            },
+            RuntimeError::Metering(_) => {
+                AbiError::LimitExceeded(format!("execution budget exhausted while executing '{}'", function))
+            }
+            e => AbiError::Trap {
+                function: function.to_string(),
+                message: e.to_string(),
+            },
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! Messages of the `json_v1alpha1` abi, as defined by `abi-schema/json_v1alpha1.schema.json`
+
+use crate::kube_cache::{CacheQuery, CacheVerb};
+use crate::kube_watch::WatchKey;
+use http::header::{HeaderName, HeaderValue};
+use kube_abi_types::v1alpha1::HttpError;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::time::Duration;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Header {
+    pub name: String,
+    pub value: String,
+}
+
+/// Payload of the `http-proxy-abi` imports
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct HttpRequest {
+    pub method: String,
+    pub uri: String,
+    #[serde(default)]
+    pub headers: Vec<Header>,
+    /// Base64 encoded body
+    #[serde(default)]
+    pub body: String,
+    #[serde(default)]
+    pub timeout_millis: Option<u64>,
+}
+
+impl HttpRequest {
+    pub fn into_request(self) -> Result<(http::Request<Vec<u8>>, Option<Duration>), String> {
+        let mut request = http::Request::new(base64::decode(&self.body).map_err(|e| format!("invalid body: {}", e))?);
+        *request.method_mut() = http::Method::try_from(self.method.as_str()).map_err(|e| e.to_string())?;
+        *request.uri_mut() = http::Uri::try_from(self.uri.as_str()).map_err(|e| e.to_string())?;
+        for header in self.headers {
+            let name = HeaderName::from_bytes(header.name.as_bytes()).map_err(|e| e.to_string())?;
+            let value = HeaderValue::from_str(&header.value).map_err(|e| e.to_string())?;
+            request.headers_mut().append(name, value);
+        }
+        Ok((request, self.timeout_millis.map(Duration::from_millis)))
+    }
+}
+
+/// Value the module is woken up with, after a `request` or as the first item of a `request_stream`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum HttpResult {
+    Response(HttpResponse),
+    Error(HttpFailure),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<Header>,
+    /// Base64 encoded body, empty for streamed responses
+    pub body: String,
+}
+
+impl From<http::Response<Vec<u8>>> for HttpResponse {
+    fn from(response: http::Response<Vec<u8>>) -> Self {
+        HttpResponse {
+            status: response.status().as_u16(),
+            headers: response
+                .headers()
+                .iter()
+                .map(|(name, value)| Header {
+                    name: name.as_str().to_string(),
+                    value: String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                })
+                .collect(),
+            body: base64::encode(response.body()),
+        }
+    }
+}
+
+/// Body chunks of a `request_stream`, following the first item
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum BodyChunk {
+    /// Base64 encoded chunk
+    Chunk(String),
+    Error(HttpFailure),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum HttpFailureKind {
+    InvalidRequest,
+    Transport,
+    Timeout,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct HttpFailure {
+    pub kind: HttpFailureKind,
+    pub message: String,
+}
+
+impl From<HttpError> for HttpFailure {
+    fn from(error: HttpError) -> Self {
+        let kind = match &error {
+            HttpError::InvalidRequest(_) => HttpFailureKind::InvalidRequest,
+            HttpError::Transport(_) => HttpFailureKind::Transport,
+            HttpError::Timeout(_) => HttpFailureKind::Timeout,
+        };
+        HttpFailure { kind, message: error.to_string() }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Resource {
+    pub api_version: String,
+    #[serde(default)]
+    pub group: String,
+    pub kind: String,
+    pub version: String,
+    #[serde(default)]
+    pub namespace: Option<String>,
+}
+
+impl From<Resource> for kube::Resource {
+    fn from(resource: Resource) -> Self {
+        kube::Resource {
+            api_version: resource.api_version,
+            group: resource.group,
+            kind: resource.kind,
+            version: resource.version,
+            namespace: resource.namespace,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ListParams {
+    #[serde(default)]
+    pub label_selector: Option<String>,
+    #[serde(default)]
+    pub field_selector: Option<String>,
+    #[serde(default)]
+    pub timeout_seconds: Option<u32>,
+    #[serde(default)]
+    pub allow_bookmarks: bool,
+    #[serde(default)]
+    pub limit: Option<u32>,
+    #[serde(default)]
+    pub continue_token: Option<String>,
+}
+
+/// Payload of the `kube-watch-abi` `watch` import
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct WatchRequest {
+    pub resource: Resource,
+    #[serde(default)]
+    pub resource_version: String,
+    #[serde(default)]
+    pub list_params: ListParams,
+}
+
+impl From<WatchRequest> for WatchKey {
+    fn from(watch_request: WatchRequest) -> Self {
+        let lp = watch_request.list_params;
+        WatchKey {
+            resource_version: watch_request.resource_version,
+            resource: watch_request.resource.into(),
+            list_params: kube::api::ListParams {
+                label_selector: lp.label_selector,
+                field_selector: lp.field_selector,
+                timeout: lp.timeout_seconds,
+                allow_bookmarks: lp.allow_bookmarks,
+                limit: lp.limit,
+                continue_token: lp.continue_token,
+            },
+            identity: Default::default(),
+        }
+    }
+}
+
+/// Payload of the `kube-cache-abi` `query` import
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct CacheRequest {
+    pub resource: Resource,
+    #[serde(flatten)]
+    pub verb: CacheRequestVerb,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "verb", rename_all = "camelCase")]
+pub(crate) enum CacheRequestVerb {
+    Get {
+        name: String,
+    },
+    List {
+        #[serde(default, rename = "labelSelector")]
+        label_selector: Option<String>,
+        #[serde(default, rename = "fieldSelector")]
+        field_selector: Option<String>,
+    },
+}
+
+impl From<CacheRequest> for CacheQuery {
+    fn from(cache_request: CacheRequest) -> Self {
+        let verb = match cache_request.verb {
+            CacheRequestVerb::Get { name } => CacheVerb::Get { name },
+            CacheRequestVerb::List { label_selector, field_selector } => CacheVerb::List { label_selector, field_selector },
+        };
+        CacheQuery {
+            resource: cache_request.resource.into(),
+            verb,
+            identity: Default::default(),
+        }
+    }
+}
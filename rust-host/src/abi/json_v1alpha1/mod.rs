@@ -0,0 +1,56 @@
+//! Language neutral abi: the payloads are JSON documents, defined by `abi-schema/json_v1alpha1.schema.json`.
+//! The imports and the exports are the same of the `rust_v1alpha1` abi.
+
+use crate::kube_watch::WatchKey;
+use crate::kube_cache::CacheQuery;
+use super::dispatcher::AsyncValue;
+use super::AbiFeatures;
+use std::time::Duration;
+
+mod data;
+
+use data::{BodyChunk, CacheRequest, HttpRequest, HttpResult, WatchRequest};
+
+pub(crate) struct WireFormat;
+
+impl super::WireFormat for WireFormat {
+    fn decode_http_request(payload: &[u8], features: AbiFeatures) -> Result<(http::Request<Vec<u8>>, Option<Duration>), String> {
+        let request: HttpRequest = serde_json::from_slice(payload).map_err(|e| e.to_string())?;
+        let (request, timeout) = request.into_request()?;
+        // Without `request-timeout`, the module doesn't expect `timeoutMillis` to be honored
+        Ok((request, timeout.filter(|_| features.request_timeout)))
+    }
+
+    fn decode_watch_request(payload: &[u8]) -> Result<WatchKey, String> {
+        let watch_request: WatchRequest = serde_json::from_slice(payload).map_err(|e| e.to_string())?;
+        Ok(watch_request.into())
+    }
+
+    fn decode_cache_request(payload: &[u8]) -> Result<CacheQuery, String> {
+        let cache_request: CacheRequest = serde_json::from_slice(payload).map_err(|e| e.to_string())?;
+        Ok(cache_request.into())
+    }
+
+    /// Without `http-errors` the errors are already replaced, so `HttpResult` and `BodyChunk`
+    /// never carry the `error` variant
+    fn encode_value(value: AsyncValue, _features: AbiFeatures) -> Result<Vec<u8>, String> {
+        match value {
+            AsyncValue::HttpResponse(response) => {
+                let result = match response {
+                    Ok(response) => HttpResult::Response(response.into()),
+                    Err(e) => HttpResult::Error(e.into()),
+                };
+                serde_json::to_vec(&result).map_err(|e| e.to_string())
+            }
+            AsyncValue::HttpBodyChunk(chunk) => {
+                let chunk = match chunk {
+                    Ok(chunk) => BodyChunk::Chunk(base64::encode(&chunk)),
+                    Err(e) => BodyChunk::Error(e.into()),
+                };
+                serde_json::to_vec(&chunk).map_err(|e| e.to_string())
+            }
+            // Watch events are already JSON documents
+            AsyncValue::WatchEvent(event) => Ok(event),
+        }
+    }
+}
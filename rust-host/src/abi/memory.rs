@@ -0,0 +1,108 @@
+//! Copies between the host and the module linear memory.
+//! It doesn't depend on the rest of the crate, so the benchmarks can include it.
+
+use std::cell::Cell;
+use wasmer_runtime::Memory;
+
+/// Copy the bytes in the module memory at the provided offset, with a single bulk copy.
+/// Returns `false` if the destination is out of the module memory.
+pub fn write_bytes(memory: &Memory, offset: u32, bytes: &[u8]) -> bool {
+    let view = memory.view::<u8>();
+    let cells: &[Cell<u8>] = match cells(&view, offset, bytes.len()) {
+        Some(cells) => cells,
+        None => return false,
+    };
+    if let Some(first) = cells.first() {
+        // Cell<u8> has the same layout of u8, and the module doesn't run while the host copies
+        unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), first.as_ptr(), bytes.len()) };
+    }
+    true
+}
+
+/// Copy `len` bytes at the provided offset out of the module memory, with a single bulk copy.
+/// Returns `None` if the source is out of the module memory.
+pub fn read_bytes(memory: &Memory, offset: u32, len: u32) -> Option<Vec<u8>> {
+    let view = memory.view::<u8>();
+    let cells = cells(&view, offset, len as usize)?;
+    let mut bytes = vec![0; cells.len()];
+    if let Some(first) = cells.first() {
+        unsafe { std::ptr::copy_nonoverlapping(first.as_ptr() as *const u8, bytes.as_mut_ptr(), bytes.len()) };
+    }
+    Some(bytes)
+}
+
+fn cells(view: &[Cell<u8>], offset: u32, len: usize) -> Option<&[Cell<u8>]> {
+    let start = offset as usize;
+    let end = start.checked_add(len)?;
+    view.get(start..end)
+}
+
+/// Frame the items of a batch, prefixing each item with its length as a little endian u32
+pub fn encode_batch(items: &[Vec<u8>]) -> Vec<u8> {
+    let mut batch = Vec::with_capacity(items.iter().map(|item| item.len() + 4).sum());
+    for item in items {
+        batch.extend_from_slice(&(item.len() as u32).to_le_bytes());
+        batch.extend_from_slice(item);
+    }
+    batch
+}
+
+/// Result of an async request in a wakeup batch
+pub struct BatchEntry {
+    pub async_request_id: u64,
+    /// `true` for stream items, `false` for futures
+    pub stream: bool,
+    /// The encoded value. `None` completes a future without a value, or closes a stream
+    pub value: Option<Vec<u8>>,
+}
+
+/// Frame the entries of a wakeup batch, each one as: the async request id as a little endian u64,
+/// a byte for the async type (`0` future, `1` stream), a byte telling if a value follows,
+/// the value length as a little endian u32 and the value
+pub fn encode_wakeup_batch(entries: &[BatchEntry]) -> Vec<u8> {
+    let size = entries.iter().map(|e| 14 + e.value.as_ref().map_or(0, Vec::len)).sum();
+    let mut batch = Vec::with_capacity(size);
+    for entry in entries {
+        batch.extend_from_slice(&entry.async_request_id.to_le_bytes());
+        batch.push(entry.stream as u8);
+        batch.push(entry.value.is_some() as u8);
+        let value = entry.value.as_deref().unwrap_or(&[]);
+        batch.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        batch.extend_from_slice(value);
+    }
+    batch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_batch_frames_each_item() {
+        let batch = encode_batch(&[b"ab".to_vec(), Vec::new()]);
+        assert_eq!(batch, vec![2, 0, 0, 0, b'a', b'b', 0, 0, 0, 0]);
+        assert!(encode_batch(&[]).is_empty());
+    }
+
+    #[test]
+    fn encode_wakeup_batch_frames_each_entry() {
+        let batch = encode_wakeup_batch(&[
+            BatchEntry { async_request_id: 7, stream: false, value: Some(b"ok".to_vec()) },
+            BatchEntry { async_request_id: 265, stream: true, value: None },
+        ]);
+        assert_eq!(
+            batch,
+            vec![
+                7, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 0, 0, 0, b'o', b'k',
+                9, 1, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0,
+            ]
+        );
+    }
+
+    #[test]
+    fn encode_wakeup_batch_keeps_empty_values() {
+        // An empty value still completes the future with a value, unlike `None`
+        let batch = encode_wakeup_batch(&[BatchEntry { async_request_id: 1, stream: false, value: Some(Vec::new()) }]);
+        assert_eq!(batch, vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0]);
+    }
+}
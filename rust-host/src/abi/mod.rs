@@ -1,30 +1,153 @@
 use crate::kube_watch::{WatchKey};
+use crate::kube_cache::CacheQuery;
+use crate::http::HttpCommand;
+use crate::delay::Clock;
+use crate::modules::{ControllerModuleMetadata, ModuleLimits};
 use serde::{Deserialize, Serialize};
 
 use tokio::sync::mpsc::UnboundedSender;
 use wasmer_runtime::{ImportObject, Instance};
-use dispatcher::AsyncType;
+use dispatcher::{AsyncResult, AsyncType, AsyncValue};
 use std::fmt::Debug;
-use crate::abi::commands::AbiCommand;
+use crate::abi::commands::ExecutorCommand;
 use std::time::Duration;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use kube_abi_types::v1alpha1::HttpError;
 
 #[cfg(feature = "abi-rust-v1alpha1")]
 pub(crate) mod rust_v1alpha1;
+#[cfg(feature = "abi-json-v1alpha1")]
+pub(crate) mod json_v1alpha1;
+mod wasm;
+mod memory;
 
 pub mod dispatcher;
 pub mod commands;
+pub mod error;
+pub mod tasks;
+pub mod compat;
 
+pub use error::AbiError;
+pub use compat::AbiDescriptor;
+
+#[derive(Clone)]
 pub struct AbiConfig {
-    pub http_command_sender: UnboundedSender<AbiCommand<http::Request<Vec<u8>>>>,
-    pub delay_command_sender: UnboundedSender<AbiCommand<Duration>>,
-    pub watch_command_sender: UnboundedSender<AbiCommand<WatchKey>>,
+    pub http_command_sender: UnboundedSender<ExecutorCommand<HttpCommand>>,
+    pub delay_command_sender: UnboundedSender<ExecutorCommand<Duration>>,
+    pub watch_command_sender: UnboundedSender<ExecutorCommand<WatchKey>>,
+    pub cache_command_sender: UnboundedSender<ExecutorCommand<CacheQuery>>,
+    /// Host wide counter, so async request ids are never reused across module reloads
+    pub async_request_counter: Arc<AtomicU64>,
+    /// Clock read by the modules, real or virtual
+    pub clock: Clock,
+}
+
+impl AbiConfig {
+    /// Cancel the async request with the provided id. Only the executor owning it will act on it
+    pub fn cancel(&self, controller_name: &str, async_request_id: u64) {
+        let _ = self.http_command_sender.send(ExecutorCommand::Cancel { controller_name: controller_name.to_string(), async_request_id });
+        let _ = self.delay_command_sender.send(ExecutorCommand::Cancel { controller_name: controller_name.to_string(), async_request_id });
+        let _ = self.watch_command_sender.send(ExecutorCommand::Cancel { controller_name: controller_name.to_string(), async_request_id });
+        let _ = self.cache_command_sender.send(ExecutorCommand::Cancel { controller_name: controller_name.to_string(), async_request_id });
+        self.clock.request_finished(controller_name, async_request_id);
+    }
+
+    /// Cancel all the outstanding watches, delays, cache queries and http requests of the provided controller
+    pub fn cancel_all(&self, controller_name: &str) {
+        let _ = self.http_command_sender.send(ExecutorCommand::CancelAll { controller_name: controller_name.to_string() });
+        let _ = self.delay_command_sender.send(ExecutorCommand::CancelAll { controller_name: controller_name.to_string() });
+        let _ = self.watch_command_sender.send(ExecutorCommand::CancelAll { controller_name: controller_name.to_string() });
+        let _ = self.cache_command_sender.send(ExecutorCommand::CancelAll { controller_name: controller_name.to_string() });
+        self.clock.requests_cancelled(controller_name);
+    }
+}
+
+/// Optional features of the abi a module opts in through its descriptor, changing the encoding of the payloads.
+/// Modules not declaring a feature, or not exporting a descriptor at all, get the encoding the abi was released with
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AbiFeatures {
+    /// `http-errors`: http results carry the transport errors
+    pub http_errors: bool,
+    /// `request-timeout`: http requests carry their timeout
+    pub request_timeout: bool,
+}
+
+impl AbiFeatures {
+    /// Names of the features, as declared in the module descriptor
+    pub const NAMES: &'static [&'static str] = &["http-errors", "request-timeout"];
+
+    pub fn from_names(names: &[String]) -> AbiFeatures {
+        AbiFeatures {
+            http_errors: names.iter().any(|name| name == "http-errors"),
+            request_timeout: names.iter().any(|name| name == "request-timeout"),
+        }
+    }
+
+    /// Replace the http errors with values the modules without `http-errors` understand:
+    /// failed requests get a `Status` response, like the API server ones, while failed streams just end
+    pub(crate) fn adapt_value(&self, value: AsyncValue) -> AsyncValue {
+        if self.http_errors {
+            return value;
+        }
+        match value {
+            AsyncValue::HttpResponse(Err(e)) => AsyncValue::HttpResponse(Ok(error_response(&e))),
+            AsyncValue::HttpBodyChunk(Err(_)) => AsyncValue::HttpBodyChunk(Ok(Vec::new())),
+            value => value,
+        }
+    }
 }
 
+/// Build the response the API server would send if it failed like the connection to it
+fn error_response(error: &HttpError) -> http::Response<Vec<u8>> {
+    let (code, reason) = match error {
+        HttpError::InvalidRequest(_) => (400, "BadRequest"),
+        HttpError::Transport(_) => (503, "ServiceUnavailable"),
+        HttpError::Timeout(_) => (504, "Timeout"),
+    };
+    let status = crate::utils::failure_status(code, reason, &error.to_string());
+    let mut response = http::Response::new(serde_json::to_vec(&status).unwrap_or_default());
+    *response.status_mut() = http::StatusCode::from_u16(code).unwrap_or(http::StatusCode::INTERNAL_SERVER_ERROR);
+    response.headers_mut().insert(http::header::CONTENT_TYPE, http::HeaderValue::from_static("application/json"));
+    response
+}
+
+/// Abi of a module. Each call into the module gets the whole execution budget of its limits
 pub trait Abi {
-    fn generate_imports(&self, controller_name: &str, abi_config: AbiConfig) -> ImportObject;
-    fn start_controller(&self, instance: &Instance) -> anyhow::Result<()>;
-    fn wakeup(&self, instance: &Instance, async_request_id: u64, async_type: AsyncType, value: Option<Vec<u8>>) -> anyhow::Result<()>;
-    fn allocate(&self, instance: &Instance, allocation_size: u32) -> anyhow::Result<u32>;
+    fn generate_imports(&self, meta: &ControllerModuleMetadata, abi_config: AbiConfig) -> ImportObject;
+    fn start_controller(&self, instance: &mut Instance) -> Result<(), AbiError>;
+    fn wakeup(&self, instance: &mut Instance, async_request_id: u64, async_type: AsyncType, value: Option<AsyncValue>) -> Result<(), AbiError>;
+    /// Wake up the stream with several items at once, with a single call if the module supports it
+    fn wakeup_stream_batch(&self, instance: &mut Instance, async_request_id: u64, values: Vec<AsyncValue>) -> Result<(), AbiError>;
+    /// Wake up the module with the results of several async requests, in the order they were produced,
+    /// with a single call if the module supports it
+    fn wakeup_batch(&self, instance: &mut Instance, results: Vec<AsyncResult>) -> Result<(), AbiError>;
+    fn allocate(&self, instance: &mut Instance, allocation_size: u32) -> Result<u32, AbiError>;
+    /// Free an allocation the module never took the ownership of, because the host failed before handing it over.
+    /// Once the wakeup function is called the payload is owned by the module: if the call traps,
+    /// the instance is dropped together with its memory
+    fn deallocate(&self, instance: &mut Instance, ptr: u32, allocation_size: u32) -> Result<(), AbiError>;
+    /// Check if the host provides the import to the modules, besides the WASI ones
+    fn provides_import(&self, namespace: &str, name: &str) -> bool;
+    /// Features of the abi a module may require in its descriptor
+    fn supported_features(&self) -> &'static [&'static str];
+    /// Encode the payloads exchanged with the module following the features it declared.
+    /// Must be called before starting the module
+    fn enable_features(&self, features: AbiFeatures);
+    /// Read the descriptor exported by the module, if any
+    fn read_descriptor(&self, instance: &mut Instance) -> Result<Option<AbiDescriptor>, AbiError>;
+}
+
+/// Encoding of the payloads exchanged with the module
+pub(crate) trait WireFormat: 'static {
+    /// Decode the payload of the `http-proxy-abi` imports, returning the request and its timeout
+    fn decode_http_request(payload: &[u8], features: AbiFeatures) -> Result<(http::Request<Vec<u8>>, Option<Duration>), String>;
+    /// Decode the payload of the `kube-watch-abi` imports
+    fn decode_watch_request(payload: &[u8]) -> Result<WatchKey, String>;
+    /// Decode the payload of the `kube-cache-abi` imports
+    fn decode_cache_request(payload: &[u8]) -> Result<CacheQuery, String>;
+    /// Encode the value the module is woken up with
+    fn encode_value(value: AsyncValue, features: AbiFeatures) -> Result<Vec<u8>, String>;
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -32,13 +155,28 @@ pub enum AbiVersion {
     #[cfg(feature = "abi-rust-v1alpha1")]
     #[serde(alias = "rust_v1alpha1")]
     RustV1Alpha1,
+    #[cfg(feature = "abi-json-v1alpha1")]
+    #[serde(alias = "json_v1alpha1")]
+    JsonV1Alpha1,
 }
 
 impl AbiVersion {
-    pub fn get_abi(&self) -> impl Abi {
+    /// Name of the abi, as written in the module manifest and descriptor
+    pub fn name(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "abi-rust-v1alpha1")]
+            AbiVersion::RustV1Alpha1 => "rust_v1alpha1",
+            #[cfg(feature = "abi-json-v1alpha1")]
+            AbiVersion::JsonV1Alpha1 => "json_v1alpha1",
+        }
+    }
+
+    pub fn get_abi(&self, limits: ModuleLimits) -> Box<dyn Abi> {
         match self {
             #[cfg(feature = "abi-rust-v1alpha1")]
-            AbiVersion::RustV1Alpha1 => rust_v1alpha1::Abi {},
+            AbiVersion::RustV1Alpha1 => Box::new(wasm::WasmAbi::<rust_v1alpha1::WireFormat>::new(limits)),
+            #[cfg(feature = "abi-json-v1alpha1")]
+            AbiVersion::JsonV1Alpha1 => Box::new(wasm::WasmAbi::<json_v1alpha1::WireFormat>::new(limits)),
         }
     }
 }
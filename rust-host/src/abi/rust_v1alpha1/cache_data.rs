@@ -0,0 +1,17 @@
+use super::watch_data::into_resource;
+use crate::kube_cache::{CacheQuery, CacheVerb};
+use kube_abi_types::v1alpha1::{CacheRequest, CacheRequestVerb};
+
+impl From<CacheRequest> for CacheQuery {
+    fn from(cache_request: CacheRequest) -> Self {
+        let verb = match cache_request.verb {
+            CacheRequestVerb::Get { name } => CacheVerb::Get { name },
+            CacheRequestVerb::List { label_selector, field_selector } => CacheVerb::List { label_selector, field_selector },
+        };
+        CacheQuery {
+            resource: into_resource(cache_request.resource),
+            verb,
+            identity: Default::default(),
+        }
+    }
+}
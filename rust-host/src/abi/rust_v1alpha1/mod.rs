@@ -1,198 +1,54 @@
-use crate::kube_watch::{WatchKey};
-
-use super::AbiConfig;
-use super::dispatcher::AsyncType;
-use std::cell::Cell;
-use tokio::sync::mpsc::UnboundedSender;
-
-mod http_data;
-mod watch_data;
-
-pub(crate) use http_data::{HttpRequest, HttpResponse};
-
-use crate::abi::rust_v1alpha1::watch_data::WatchRequest;
-use wasmer_runtime::*;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
-use std::fmt::Debug;
-use crate::abi::commands::AbiCommand;
+//! The first abi, tailored to the Rust guests: the payloads are the bincode encoding
+//! of the `kube-abi-types` structs, shared with the guest library.
+
+use crate::kube_watch::WatchKey;
+use crate::kube_cache::CacheQuery;
+use super::dispatcher::AsyncValue;
+use super::AbiFeatures;
+use kube_abi_types::v1alpha1::{CacheRequest, HttpError, HttpRequest, HttpResponse, TimedHttpRequest, WatchRequest};
 use std::time::Duration;
 
-pub(crate) struct Abi {}
-
-impl super::Abi for Abi {
-    fn generate_imports(&self, controller_name: &str, abi_config: AbiConfig) -> ImportObject {
-        let counter = Arc::new(AtomicU64::new(0));
-        let request_ctx = AbiMethodCtx::new(controller_name, abi_config.http_command_sender, counter.clone());
-        let delay_ctx = AbiMethodCtx::new(controller_name, abi_config.delay_command_sender, counter.clone());
-        let watch_ctx = AbiMethodCtx::new(controller_name, abi_config.watch_command_sender, counter.clone());
-        imports! {
-            "http-proxy-abi" => {
-                "request" => func!(move |ctx: &mut Ctx, ptr: WasmPtr<u8, Array>, size: u32| -> u64 {
-                    request_ctx.request_impl(ctx, ptr, size)
-                }),
-            },
-            "delay-abi" => {
-                "delay" => func!(move |ctx: &mut Ctx, millis: u64| -> u64 {
-                    delay_ctx.delay_impl(ctx, millis)
-                }),
-            },
-            "kube-watch-abi" => {
-                "watch" => func!(move |ctx: &mut Ctx, ptr: WasmPtr<u8, Array>, size: u32| -> u64 {
-                    watch_ctx.watch_impl(ctx, ptr, size)
-                }),
-            }
-        }
-    }
-
-    fn start_controller(&self, instance: &Instance) -> anyhow::Result<()> {
-        instance
-            .exports
-            .get::<Func<(), ()>>("run")?
-            .call()
-            .unwrap(); //TODO better error management
-
-        Ok(())
-    }
-
-    fn wakeup(&self, instance: &Instance, async_request_id: u64, async_type: AsyncType, value: Option<Vec<u8>>) -> anyhow::Result<()> {
-        let (memory_location_ptr, memory_location_size) = match value {
-            None => (std::ptr::null::<*const u32>() as u32, 0),
-            Some(event) => {
-                let memory_location_size = event.len();
-                let memory_location_ptr = self.allocate(instance, memory_location_size as u32)?;
-
-                let allocation_wasm_ptr: WasmPtr<u8, Array> = WasmPtr::new(memory_location_ptr);
-                let memory_cell = allocation_wasm_ptr
-                    .deref(instance.context().memory(0), 0, memory_location_size as u32)
-                    .expect("Unable to retrieve memory cell to write event");
-                for (i, b) in event.iter().enumerate() {
-                    memory_cell[i].set(*b);
-                }
-
-                (memory_location_ptr, memory_location_size)
+mod watch_data;
+mod cache_data;
+
+pub(crate) struct WireFormat;
+
+impl super::WireFormat for WireFormat {
+    /// Modules declaring `request-timeout` send a `TimedHttpRequest`, the others the bare `HttpRequest`
+    fn decode_http_request(payload: &[u8], features: AbiFeatures) -> Result<(http::Request<Vec<u8>>, Option<Duration>), String> {
+        let request: TimedHttpRequest = if features.request_timeout {
+            bincode::deserialize(payload).map_err(|e| e.to_string())?
+        } else {
+            TimedHttpRequest {
+                request: bincode::deserialize::<HttpRequest>(payload).map_err(|e| e.to_string())?,
+                timeout: None,
             }
         };
-
-        let wakeup_fn = match async_type {
-            AsyncType::Future => instance.exports.get::<Func<(u64, u32, u32), ()>>("wakeup_future")?,
-            AsyncType::Stream => instance.exports.get::<Func<(u64, u32, u32), ()>>("wakeup_stream")?,
-        };
-
-        wakeup_fn
-            .call(async_request_id, memory_location_ptr, memory_location_size as u32)
-            .unwrap(); //TODO better error management
-
-        Ok(())
-    }
-
-    fn allocate(&self, instance: &Instance, allocation_size: u32) -> anyhow::Result<u32> {
-        Ok(instance
-            .exports
-            .get::<Func<u32, u32>>("allocate")?
-            .call(allocation_size)
-            .unwrap() //TODO better error management
-        )
+        Ok((request.request.into(), request.timeout))
     }
-}
-
-struct AbiMethodCtx<T: Sized + Debug> {
-    controller_name: String,
-    command_sender: UnboundedSender<AbiCommand<T>>,
-    async_request_counter: Arc<AtomicU64>,
-}
 
-impl <T: Sized + Debug> AbiMethodCtx<T> {
-    fn new(controller_name: &str, command_sender: UnboundedSender<AbiCommand<T>>, async_request_counter: Arc<AtomicU64>) -> Self {
-        AbiMethodCtx {
-            controller_name: controller_name.to_string(),
-            command_sender,
-            async_request_counter
-        }
+    fn decode_watch_request(payload: &[u8]) -> Result<WatchKey, String> {
+        let watch_request: WatchRequest = bincode::deserialize(payload).map_err(|e| e.to_string())?;
+        Ok(watch_request.into())
     }
 
-    fn generate_async_request_id(&self) -> u64 {
-        (&self.async_request_counter).fetch_add(1, Ordering::SeqCst)
+    fn decode_cache_request(payload: &[u8]) -> Result<CacheQuery, String> {
+        let cache_request: CacheRequest = bincode::deserialize(payload).map_err(|e| e.to_string())?;
+        Ok(cache_request.into())
     }
-}
 
-impl AbiMethodCtx<http::Request<Vec<u8>>> {
-    fn request_impl(
-        &self,
-        ctx: &mut Ctx,
-        ptr: WasmPtr<u8, Array>,
-        size: u32
-    ) -> u64 {
-        let inner_req_bytes: Vec<u8> = ptr
-            .deref(ctx.memory(0), 0, size)
-            .unwrap()
-            .iter()
-            .map(Cell::get)
-            .collect();
-
-        // Get the request
-        let inner_request: HttpRequest = bincode::deserialize(&inner_req_bytes).unwrap();
-
-        let async_request_id = self.generate_async_request_id();
-
-        self.command_sender
-            .send(AbiCommand {
-                async_request_id,
-                controller_name: self.controller_name.clone(),
-                value: inner_request.into()
-            })
-            .unwrap();
-
-        async_request_id
-    }
-}
-
-impl AbiMethodCtx<Duration> {
-    fn delay_impl(
-        &self,
-        _ctx: &mut Ctx,
-        millis: u64
-    ) -> u64 {
-        let async_request_id = self.generate_async_request_id();
-
-        self.command_sender
-            .send(AbiCommand {
-                async_request_id,
-                controller_name: self.controller_name.clone(),
-                value: Duration::from_millis(millis)
-            })
-            .unwrap();
-
-        async_request_id
-    }
-}
-
-impl AbiMethodCtx<WatchKey> {
-    fn watch_impl(
-        &self,
-        ctx: &mut Ctx,
-        ptr: WasmPtr<u8, Array>,
-        size: u32
-    ) -> u64 {
-        let watch_req_bytes: Vec<u8> = ptr
-            .deref(ctx.memory(0), 0, size)
-            .unwrap()
-            .iter()
-            .map(Cell::get)
-            .collect();
-
-        let watch_request: WatchRequest = bincode::deserialize(&watch_req_bytes).unwrap();
-        let async_request_id = self.generate_async_request_id();
-        debug!("Received new watch request '{:?}' from '{}'. Assigned id: {}", &watch_request, &self.controller_name, &async_request_id);
-
-        self.command_sender
-            .send(AbiCommand {
-                async_request_id,
-                controller_name: self.controller_name.clone(),
-                value: watch_request.into()
-            })
-            .unwrap();
-
-        async_request_id
+    /// Modules declaring `http-errors` get `Result<HttpResponse, HttpError>` and `Result<Vec<u8>, HttpError>` chunks,
+    /// the others get the bare `HttpResponse` and raw chunks, as their errors were already replaced
+    fn encode_value(value: AsyncValue, features: AbiFeatures) -> Result<Vec<u8>, String> {
+        match value {
+            AsyncValue::HttpResponse(response) => match response.map(HttpResponse::from) {
+                Ok(response) if !features.http_errors => bincode::serialize(&response).map_err(|e| e.to_string()),
+                response => bincode::serialize(&response).map_err(|e| e.to_string()),
+            },
+            AsyncValue::HttpBodyChunk(Ok(chunk)) if !features.http_errors => Ok(chunk),
+            AsyncValue::HttpBodyChunk(chunk) => bincode::serialize(&chunk).map_err(|e| e.to_string()),
+            // Watch events are sent as received from the API server
+            AsyncValue::WatchEvent(event) => Ok(event),
+        }
     }
 }
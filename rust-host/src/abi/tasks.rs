@@ -0,0 +1,58 @@
+use futures::future::{AbortHandle, Abortable};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+/// Tracks the tasks spawned by an executor for each async request,
+/// so they can be aborted when the controller goes away
+#[derive(Clone, Default)]
+pub struct InFlightTasks {
+    handles: Arc<Mutex<HashMap<(String, u64), AbortHandle>>>,
+}
+
+impl InFlightTasks {
+    /// Spawn the provided future on the tokio runtime, tracking it with the
+    /// provided controller name and async request id
+    pub fn spawn<F>(&self, controller_name: String, async_request_id: u64, fut: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        let key = (controller_name, async_request_id);
+        self.handles
+            .lock()
+            .unwrap()
+            .insert(key.clone(), abort_handle);
+
+        let handles = self.handles.clone();
+        tokio::spawn(async move {
+            let _ = Abortable::new(fut, abort_registration).await;
+            handles.lock().unwrap().remove(&key);
+        });
+    }
+
+    /// Abort the task of the provided async request, if any
+    pub fn abort(&self, controller_name: &str, async_request_id: u64) {
+        let key = (controller_name.to_string(), async_request_id);
+        if let Some(handle) = self.handles.lock().unwrap().remove(&key) {
+            debug!("Aborting task ({}, {})", &key.0, &key.1);
+            handle.abort();
+        }
+    }
+
+    /// Abort all the tasks of the provided controller
+    pub fn abort_all(&self, controller_name: &str) {
+        let mut handles = self.handles.lock().unwrap();
+        let keys: Vec<(String, u64)> = handles
+            .keys()
+            .filter(|(name, _)| name == controller_name)
+            .cloned()
+            .collect();
+        for key in keys {
+            if let Some(handle) = handles.remove(&key) {
+                debug!("Aborting task ({}, {})", &key.0, &key.1);
+                handle.abort();
+            }
+        }
+    }
+}
@@ -0,0 +1,575 @@
+//! The imports and exports shared by the abi versions, which differ only in the encoding of the payloads
+
+use crate::delay::Clock;
+use crate::kube_watch::WatchKey;
+
+use super::{memory, AbiConfig, AbiDescriptor, AbiFeatures, WireFormat};
+use super::error::AbiError;
+use super::dispatcher::{AsyncResult, AsyncType, AsyncValue};
+use std::marker::PhantomData;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::kube_cache::CacheQuery;
+use crate::http::HttpCommand;
+use wasmer_runtime::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::fmt::Debug;
+use crate::abi::commands::{AbiCommand, ExecutorCommand};
+use crate::modules::{ControllerModuleMetadata, ModuleIdentity, ModuleLimits, ModulePolicy, RateLimit};
+use std::time::Duration;
+
+/// Imports provided to the modules, as `(namespace, name)`
+const HOST_IMPORTS: &[(&str, &str)] = &[
+    ("http-proxy-abi", "request"),
+    ("http-proxy-abi", "request_stream"),
+    ("delay-abi", "delay"),
+    ("delay-abi", "reset"),
+    ("kube-watch-abi", "watch"),
+    ("kube-cache-abi", "query"),
+    ("async-abi", "cancel"),
+    ("abi-info", "supports"),
+    ("time-abi", "now"),
+];
+
+/// Abi of the modules compiled to WASI, encoding the payloads with the provided wire format
+pub(crate) struct WasmAbi<F: WireFormat> {
+    wire_format: PhantomData<F>,
+    /// Features declared by the module, shared with its imports
+    features: Arc<RwLock<AbiFeatures>>,
+    /// Limits of the module, refilling its execution budget before each call into it
+    limits: ModuleLimits,
+}
+
+impl<F: WireFormat> WasmAbi<F> {
+    pub(crate) fn new(limits: ModuleLimits) -> Self {
+        WasmAbi {
+            wire_format: PhantomData,
+            features: Arc::new(RwLock::new(AbiFeatures::default())),
+            limits,
+        }
+    }
+
+    fn encode_value(&self, value: AsyncValue) -> Result<Vec<u8>, AbiError> {
+        let features = read_features(&self.features);
+        F::encode_value(features.adapt_value(value), features).map_err(AbiError::Encoding)
+    }
+
+    /// Allocate the space for the payload in the module memory and copy it there, returning its location
+    fn copy_to_module(&self, instance: &mut Instance, payload: &[u8]) -> Result<u32, AbiError> {
+        let size = payload.len() as u32;
+        let ptr = self.allocate(instance, size)?;
+        if !memory::write_bytes(instance.context().memory(0), ptr, payload) {
+            if let Err(e) = self.deallocate(instance, ptr, size) {
+                warn!("Cannot free the allocation at {}: {}", ptr, e);
+            }
+            return Err(AbiError::Allocation {
+                size,
+                message: format!("allocated pointer {} is out of the module memory", ptr),
+            });
+        }
+        Ok(ptr)
+    }
+}
+
+impl<F: WireFormat> super::Abi for WasmAbi<F> {
+    fn generate_imports(&self, meta: &ControllerModuleMetadata, abi_config: AbiConfig) -> ImportObject {
+        let controller_name = &meta.name;
+        let policy = Arc::new(meta.policy.clone());
+        let identity = Arc::new(meta.identity.clone());
+        let counter = abi_config.async_request_counter.clone();
+        let request_features = self.features.clone();
+        let request_stream_features = self.features.clone();
+        let request_ctx = AbiMethodCtx::new(controller_name, abi_config.http_command_sender.clone(), counter.clone(), policy.clone(), identity.clone())
+            .tracked_by(abi_config.clock.clone());
+        let request_stream_ctx = AbiMethodCtx::new(controller_name, abi_config.http_command_sender.clone(), counter.clone(), policy.clone(), identity.clone())
+            .tracked_by(abi_config.clock.clone());
+        let delay_ctx = AbiMethodCtx::new(controller_name, abi_config.delay_command_sender.clone(), counter.clone(), policy.clone(), identity.clone());
+        let reset_delay_ctx = AbiMethodCtx::new(controller_name, abi_config.delay_command_sender.clone(), counter.clone(), policy.clone(), identity.clone());
+        let watch_ctx = AbiMethodCtx::new(controller_name, abi_config.watch_command_sender.clone(), counter.clone(), policy.clone(), identity.clone());
+        let cache_ctx = AbiMethodCtx::new(controller_name, abi_config.cache_command_sender.clone(), counter.clone(), policy.clone(), identity.clone())
+            .tracked_by(abi_config.clock.clone());
+        let rate_limit = meta.limits.rate_limit;
+        let cancel_controller_name = controller_name.clone();
+        let cancel_abi_config = abi_config.clone();
+        let time_controller_name = controller_name.clone();
+        let clock = abi_config.clock.clone();
+        imports! {
+            "http-proxy-abi" => {
+                "request" => func!(move |ctx: &mut Ctx, ptr: WasmPtr<u8, Array>, size: u32| -> Result<u64, AbiError> {
+                    request_ctx.request_impl::<F>(ctx, ptr, size, false, rate_limit, read_features(&request_features))
+                }),
+                "request_stream" => func!(move |ctx: &mut Ctx, ptr: WasmPtr<u8, Array>, size: u32| -> Result<u64, AbiError> {
+                    request_stream_ctx.request_impl::<F>(ctx, ptr, size, true, rate_limit, read_features(&request_stream_features))
+                }),
+            },
+            "delay-abi" => {
+                "delay" => func!(move |ctx: &mut Ctx, millis: u64| -> Result<u64, AbiError> {
+                    delay_ctx.delay_impl(ctx, millis)
+                }),
+                "reset" => func!(move |ctx: &mut Ctx, timer_id: u64, millis: u64| -> Result<u64, AbiError> {
+                    reset_delay_ctx.reset_delay_impl(ctx, timer_id, millis)
+                }),
+            },
+            "kube-watch-abi" => {
+                "watch" => func!(move |ctx: &mut Ctx, ptr: WasmPtr<u8, Array>, size: u32| -> Result<u64, AbiError> {
+                    watch_ctx.watch_impl::<F>(ctx, ptr, size)
+                }),
+            },
+            "kube-cache-abi" => {
+                "query" => func!(move |ctx: &mut Ctx, ptr: WasmPtr<u8, Array>, size: u32| -> Result<u64, AbiError> {
+                    cache_ctx.cache_query_impl::<F>(ctx, ptr, size)
+                }),
+            },
+            "async-abi" => {
+                "cancel" => func!(move |_ctx: &mut Ctx, async_request_id: u64| {
+                    debug!("Received cancel for ({}, {})", &cancel_controller_name, async_request_id);
+                    cancel_abi_config.cancel(&cancel_controller_name, async_request_id)
+                }),
+            },
+            "abi-info" => {
+                "supports" => func!(move |ctx: &mut Ctx, ptr: WasmPtr<u8, Array>, size: u32| -> Result<u32, AbiError> {
+                    supports_impl(ctx, ptr, size)
+                }),
+            },
+            "time-abi" => {
+                "now" => func!(move |_ctx: &mut Ctx| -> u64 {
+                    clock.now(&time_controller_name).as_nanos() as u64
+                }),
+            }
+        }
+    }
+
+    fn provides_import(&self, namespace: &str, name: &str) -> bool {
+        HOST_IMPORTS.contains(&(namespace, name))
+    }
+
+    fn supported_features(&self) -> &'static [&'static str] {
+        AbiFeatures::NAMES
+    }
+
+    fn enable_features(&self, features: AbiFeatures) {
+        match self.features.write() {
+            Ok(mut enabled) => *enabled = features,
+            Err(poisoned) => *poisoned.into_inner() = features,
+        }
+    }
+
+    fn read_descriptor(&self, instance: &mut Instance) -> Result<Option<AbiDescriptor>, AbiError> {
+        if instance.exports.get::<Func<(), u64>>("abi_descriptor").is_err() {
+            return Ok(None);
+        }
+        self.limits.reset_fuel(instance);
+        // The pointer is in the high 32 bits, the length in the low 32 bits
+        let location = instance
+            .exports
+            .get::<Func<(), u64>>("abi_descriptor")
+            .map_err(|_| AbiError::MissingExport("abi_descriptor".to_string()))?
+            .call()
+            .map_err(|e| AbiError::from_runtime_error("abi_descriptor", e))?;
+        let ptr: WasmPtr<u8, Array> = WasmPtr::new((location >> 32) as u32);
+        let descriptor = read_payload(instance.context(), ptr, location as u32)?;
+
+        serde_json::from_slice(&descriptor)
+            .map(Some)
+            .map_err(|e| AbiError::BadPayload(format!("cannot decode the abi descriptor: {}", e)))
+    }
+
+    fn start_controller(&self, instance: &mut Instance) -> Result<(), AbiError> {
+        self.limits.reset_fuel(instance);
+        instance
+            .exports
+            .get::<Func<(), ()>>("run")
+            .map_err(|_| AbiError::MissingExport("run".to_string()))?
+            .call()
+            .map_err(|e| AbiError::from_runtime_error("run", e))
+    }
+
+    fn wakeup(&self, instance: &mut Instance, async_request_id: u64, async_type: AsyncType, value: Option<AsyncValue>) -> Result<(), AbiError> {
+        let wakeup_fn_name = match async_type {
+            AsyncType::Future => "wakeup_future",
+            AsyncType::Stream => "wakeup_stream",
+        };
+        // Check the export before copying the payload, which the module would never free
+        if instance.exports.get::<Func<(u64, u32, u32), ()>>(wakeup_fn_name).is_err() {
+            return Err(AbiError::MissingExport(wakeup_fn_name.to_string()));
+        }
+
+        let (memory_location_ptr, memory_location_size) = match value {
+            None => (std::ptr::null::<*const u32>() as u32, 0),
+            Some(value) => {
+                let event = self.encode_value(value)?;
+                (self.copy_to_module(instance, &event)?, event.len() as u32)
+            }
+        };
+
+        self.limits.reset_fuel(instance);
+        instance
+            .exports
+            .get::<Func<(u64, u32, u32), ()>>(wakeup_fn_name)
+            .map_err(|_| AbiError::MissingExport(wakeup_fn_name.to_string()))?
+            .call(async_request_id, memory_location_ptr, memory_location_size)
+            .map_err(|e| AbiError::from_runtime_error(wakeup_fn_name, e))
+    }
+
+    fn wakeup_stream_batch(&self, instance: &mut Instance, async_request_id: u64, values: Vec<AsyncValue>) -> Result<(), AbiError> {
+        // Modules built before the batch export are woken up once per item, each call with its own execution budget
+        if instance.exports.get::<Func<(u64, u32, u32), ()>>("wakeup_stream_batch").is_err() {
+            for value in values {
+                self.wakeup(instance, async_request_id, AsyncType::Stream, Some(value))?;
+            }
+            return Ok(());
+        }
+
+        let items = values
+            .into_iter()
+            .map(|value| self.encode_value(value))
+            .collect::<Result<Vec<Vec<u8>>, AbiError>>()?;
+        let batch = memory::encode_batch(&items);
+        let batch_ptr = self.copy_to_module(instance, &batch)?;
+
+        self.limits.reset_fuel(instance);
+        instance
+            .exports
+            .get::<Func<(u64, u32, u32), ()>>("wakeup_stream_batch")
+            .map_err(|_| AbiError::MissingExport("wakeup_stream_batch".to_string()))?
+            .call(async_request_id, batch_ptr, batch.len() as u32)
+            .map_err(|e| AbiError::from_runtime_error("wakeup_stream_batch", e))
+    }
+
+    fn wakeup_batch(&self, instance: &mut Instance, mut results: Vec<AsyncResult>) -> Result<(), AbiError> {
+        if results.len() == 1 {
+            let result = results.pop().unwrap();
+            return self.wakeup(instance, result.async_request_id, result.async_type, result.value);
+        }
+        // Modules built before the batch export are woken up once per result, or per run of stream items
+        if instance.exports.get::<Func<(u32, u32), ()>>("wakeup_batch").is_err() {
+            return self.wakeup_each(instance, results);
+        }
+
+        let entries = results
+            .into_iter()
+            .map(|result| {
+                Ok(memory::BatchEntry {
+                    async_request_id: result.async_request_id,
+                    stream: result.async_type == AsyncType::Stream,
+                    value: result.value.map(|value| self.encode_value(value)).transpose()?,
+                })
+            })
+            .collect::<Result<Vec<memory::BatchEntry>, AbiError>>()?;
+        let batch = memory::encode_wakeup_batch(&entries);
+        let batch_ptr = self.copy_to_module(instance, &batch)?;
+
+        self.limits.reset_fuel(instance);
+        instance
+            .exports
+            .get::<Func<(u32, u32), ()>>("wakeup_batch")
+            .map_err(|_| AbiError::MissingExport("wakeup_batch".to_string()))?
+            .call(batch_ptr, batch.len() as u32)
+            .map_err(|e| AbiError::from_runtime_error("wakeup_batch", e))
+    }
+
+    fn allocate(&self, instance: &mut Instance, allocation_size: u32) -> Result<u32, AbiError> {
+        self.limits.reset_fuel(instance);
+        let ptr = instance
+            .exports
+            .get::<Func<u32, u32>>("allocate")
+            .map_err(|_| AbiError::MissingExport("allocate".to_string()))?
+            .call(allocation_size)
+            .map_err(|e| AbiError::Allocation {
+                size: allocation_size,
+                message: AbiError::from_runtime_error("allocate", e).to_string(),
+            })?;
+        if ptr == 0 {
+            return Err(AbiError::Allocation {
+                size: allocation_size,
+                message: "the module returned a null pointer".to_string(),
+            });
+        }
+        Ok(ptr)
+    }
+
+    /// Wake up the module once per result, merging the consecutive items of the same stream
+    fn wakeup_each(&self, instance: &mut Instance, results: Vec<AsyncResult>) -> Result<(), AbiError> {
+        let mut results = results.into_iter().peekable();
+        while let Some(result) = results.next() {
+            let first_item = match result.value {
+                Some(value) if result.async_type == AsyncType::Stream => value,
+                value => {
+                    self.wakeup(instance, result.async_request_id, result.async_type, value)?;
+                    continue;
+                }
+            };
+
+            let mut items = vec![first_item];
+            while let Some(next) = results.peek() {
+                if next.async_request_id != result.async_request_id || next.async_type != AsyncType::Stream || next.value.is_none() {
+                    break;
+                }
+                items.extend(results.next().unwrap().value);
+            }
+            if items.len() == 1 {
+                self.wakeup(instance, result.async_request_id, AsyncType::Stream, items.pop())?;
+            } else {
+                self.wakeup_stream_batch(instance, result.async_request_id, items)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn deallocate(&self, instance: &mut Instance, ptr: u32, allocation_size: u32) -> Result<(), AbiError> {
+        // Modules built before the deallocate export leak the allocation
+        if instance.exports.get::<Func<(u32, u32), ()>>("deallocate").is_err() {
+            debug!("The module doesn't export 'deallocate', leaking {} bytes at {}", allocation_size, ptr);
+            return Ok(());
+        }
+        self.limits.reset_fuel(instance);
+        instance
+            .exports
+            .get::<Func<(u32, u32), ()>>("deallocate")
+            .map_err(|_| AbiError::MissingExport("deallocate".to_string()))?
+            .call(ptr, allocation_size)
+            .map_err(|e| AbiError::from_runtime_error("deallocate", e))
+    }
+}
+
+struct AbiMethodCtx<T: Sized + Debug> {
+    controller_name: String,
+    command_sender: UnboundedSender<ExecutorCommand<T>>,
+    async_request_counter: Arc<AtomicU64>,
+    policy: Arc<ModulePolicy>,
+    identity: Arc<ModuleIdentity>,
+    /// Clock tracking the requests in flight, if the module waits for their results before going idle
+    clock: Option<Clock>,
+}
+
+impl <T: Sized + Debug> AbiMethodCtx<T> {
+    fn new(controller_name: &str, command_sender: UnboundedSender<ExecutorCommand<T>>, async_request_counter: Arc<AtomicU64>, policy: Arc<ModulePolicy>, identity: Arc<ModuleIdentity>) -> Self {
+        AbiMethodCtx {
+            controller_name: controller_name.to_string(),
+            command_sender,
+            async_request_counter,
+            policy,
+            identity,
+            clock: None,
+        }
+    }
+
+    /// Track the requests sent through this context as in flight on the module clock,
+    /// so delays don't fire in virtual time while the module waits for them
+    fn tracked_by(mut self, clock: Clock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    fn generate_async_request_id(&self) -> u64 {
+        (&self.async_request_counter).fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Send the command to the executor, or let the executor deny it if the policy check failed
+    fn send_command(&self, async_request_id: u64, value: T, policy_check: Result<(), String>) -> Result<(), AbiError> {
+        let command = AbiCommand {
+            async_request_id,
+            controller_name: self.controller_name.clone(),
+            value
+        };
+        if let Some(clock) = &self.clock {
+            clock.request_started(&self.controller_name, async_request_id);
+        }
+        let command = match policy_check {
+            Ok(()) => command.into(),
+            Err(message) => {
+                warn!("Denied request {} from '{}': {}", async_request_id, &self.controller_name, &message);
+                ExecutorCommand::Deny { command, message }
+            }
+        };
+
+        self.command_sender
+            .send(command)
+            .map_err(|_| AbiError::ExecutorStopped(format!("cannot send the async request {}", async_request_id)))
+    }
+}
+
+impl AbiMethodCtx<HttpCommand> {
+    fn request_impl<F: WireFormat>(
+        &self,
+        ctx: &mut Ctx,
+        ptr: WasmPtr<u8, Array>,
+        size: u32,
+        streaming: bool,
+        rate_limit: Option<RateLimit>,
+        features: AbiFeatures,
+    ) -> Result<u64, AbiError> {
+        let inner_req_bytes = read_payload(ctx, ptr, size)?;
+
+        // Get the request
+        let (mut inner_request, timeout) = F::decode_http_request(&inner_req_bytes, features)
+            .map_err(|e| AbiError::BadPayload(format!("cannot decode the http request: {}", e)))?;
+
+        let async_request_id = self.generate_async_request_id();
+
+        let policy_check = self.identity
+            .apply(&mut inner_request)
+            .and_then(|_| self.policy.check_request(&inner_request));
+        self.send_command(async_request_id, HttpCommand { request: inner_request, streaming, timeout, rate_limit, retry: None }, policy_check)?;
+
+        Ok(async_request_id)
+    }
+}
+
+impl AbiMethodCtx<Duration> {
+    fn delay_impl(
+        &self,
+        _ctx: &mut Ctx,
+        millis: u64
+    ) -> Result<u64, AbiError> {
+        let async_request_id = self.generate_async_request_id();
+
+        self.send_command(async_request_id, Duration::from_millis(millis), Ok(()))?;
+
+        Ok(async_request_id)
+    }
+
+    /// Replace the timer with a new one, so a result of the old timer already on its way can be told apart
+    fn reset_delay_impl(
+        &self,
+        ctx: &mut Ctx,
+        timer_id: u64,
+        millis: u64
+    ) -> Result<u64, AbiError> {
+        let _ = self.command_sender.send(ExecutorCommand::Cancel {
+            controller_name: self.controller_name.clone(),
+            async_request_id: timer_id,
+        });
+        self.delay_impl(ctx, millis)
+    }
+}
+
+impl AbiMethodCtx<WatchKey> {
+    fn watch_impl<F: WireFormat>(
+        &self,
+        ctx: &mut Ctx,
+        ptr: WasmPtr<u8, Array>,
+        size: u32
+    ) -> Result<u64, AbiError> {
+        let watch_req_bytes = read_payload(ctx, ptr, size)?;
+
+        let mut watch_key = F::decode_watch_request(&watch_req_bytes)
+            .map_err(|e| AbiError::BadPayload(format!("cannot decode the watch request: {}", e)))?;
+        let async_request_id = self.generate_async_request_id();
+        debug!("Received new watch request '{:?}' from '{}'. Assigned id: {}", &watch_key, &self.controller_name, &async_request_id);
+
+        watch_key.identity = self.identity.as_ref().clone();
+        let policy_check = self.policy.check_watch(&watch_key);
+        self.send_command(async_request_id, watch_key, policy_check)?;
+
+        Ok(async_request_id)
+    }
+}
+
+impl AbiMethodCtx<CacheQuery> {
+    fn cache_query_impl<F: WireFormat>(
+        &self,
+        ctx: &mut Ctx,
+        ptr: WasmPtr<u8, Array>,
+        size: u32
+    ) -> Result<u64, AbiError> {
+        let cache_req_bytes = read_payload(ctx, ptr, size)?;
+
+        let mut query = F::decode_cache_request(&cache_req_bytes)
+            .map_err(|e| AbiError::BadPayload(format!("cannot decode the cache request: {}", e)))?;
+        let async_request_id = self.generate_async_request_id();
+        debug!("Received new cache request '{:?}' from '{}'. Assigned id: {}", &query, &self.controller_name, &async_request_id);
+
+        query.identity = self.identity.as_ref().clone();
+        let policy_check = self.policy.check_cache_query(&query);
+        self.send_command(async_request_id, query, policy_check)?;
+
+        Ok(async_request_id)
+    }
+}
+
+/// Check if the host provides the capability named in the payload, either an import like `delay-abi.delay` or a feature
+fn supports_impl(ctx: &mut Ctx, ptr: WasmPtr<u8, Array>, size: u32) -> Result<u32, AbiError> {
+    let capability = read_payload(ctx, ptr, size)?;
+    let capability = std::str::from_utf8(&capability)
+        .map_err(|e| AbiError::BadPayload(format!("cannot decode the capability name: {}", e)))?;
+
+    let supported = AbiFeatures::NAMES.contains(&capability)
+        || HOST_IMPORTS.iter().any(|(namespace, name)| capability == format!("{}.{}", namespace, name));
+    Ok(supported as u32)
+}
+
+/// Features enabled for the module. A panic while holding the lock cannot leave them half written
+fn read_features(features: &RwLock<AbiFeatures>) -> AbiFeatures {
+    match features.read() {
+        Ok(features) => *features,
+        Err(poisoned) => *poisoned.into_inner(),
+    }
+}
+
+/// Copy the payload the module wants to send to the host out of the module memory
+fn read_payload(ctx: &Ctx, ptr: WasmPtr<u8, Array>, size: u32) -> Result<Vec<u8>, AbiError> {
+    memory::read_bytes(ctx.memory(0), ptr.offset(), size)
+        .ok_or_else(|| AbiError::BadPayload(format!("payload at {} with size {} is out of the module memory", ptr.offset(), size)))
+}
+
+#[cfg(all(test, feature = "abi-rust-v1alpha1"))]
+mod tests {
+    use super::*;
+    use crate::abi::{rust_v1alpha1, Abi};
+
+    /// Module built before the batch exports, burning the same fuel on each wakeup
+    const LEGACY_MODULE: &str = r#"
+        (module
+          (memory (export "memory") 1 1)
+          (global $next (mut i32) (i32.const 1024))
+          (func (export "allocate") (param $size i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $next))
+            (global.set $next (i32.add (global.get $next) (local.get $size)))
+            (local.get $ptr))
+          (func $work (local $i i32)
+            (loop $again
+              (local.set $i (i32.add (local.get $i) (i32.const 1)))
+              (br_if $again (i32.lt_u (local.get $i) (i32.const 100)))))
+          (func (export "wakeup_future") (param i64 i32 i32) (call $work))
+          (func (export "wakeup_stream") (param i64 i32 i32) (call $work)))
+    "#;
+
+    fn instantiate(fuel_per_call: u64) -> (WasmAbi<rust_v1alpha1::WireFormat>, Instance) {
+        let limits = ModuleLimits {
+            fuel_per_call: Some(fuel_per_call),
+            ..ModuleLimits::default()
+        };
+        let wasm = wabt::wat2wasm(LEGACY_MODULE).unwrap();
+        let instance = limits.compile(&wasm).unwrap().instantiate(&ImportObject::new()).unwrap();
+        (WasmAbi::new(limits), instance)
+    }
+
+    fn watch_event(async_request_id: u64, async_type: AsyncType) -> AsyncResult {
+        AsyncResult {
+            controller_name: "test".to_string(),
+            async_request_id,
+            async_type,
+            value: Some(AsyncValue::WatchEvent(b"{}".to_vec())),
+        }
+    }
+
+    #[test]
+    fn wakeup_is_limited_by_the_budget() {
+        let (abi, mut instance) = instantiate(100);
+        let result = abi.wakeup(&mut instance, 1, AsyncType::Future, None);
+        assert!(matches!(result, Err(AbiError::LimitExceeded(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn legacy_wakeups_of_a_batch_get_a_budget_each() {
+        // Enough for a single wakeup, not for the whole batch
+        let (abi, mut instance) = instantiate(2_000);
+        let mut results: Vec<AsyncResult> = (1..=16).map(|id| watch_event(id, AsyncType::Future)).collect();
+        results.extend((0..16).map(|_| watch_event(100, AsyncType::Stream)));
+
+        abi.wakeup_batch(&mut instance, results).unwrap();
+    }
+}
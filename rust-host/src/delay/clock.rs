@@ -0,0 +1,160 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/// Monotonic clock the modules read through the `time-abi`.
+///
+/// In virtual time each module has its own clock, starting at zero and advanced by the delay executor
+/// when it fires the module delays, so time never passes while the module is running or waiting for the API server:
+/// the delays fire only once the module processed its queued wakeups and has no host requests in flight.
+#[derive(Clone)]
+pub struct Clock {
+    origin: Instant,
+    virtual_time: Option<Arc<VirtualTime>>,
+}
+
+struct VirtualTime {
+    now: Mutex<HashMap<String, Duration>>,
+    /// Http requests and cache queries of each module waiting for their result
+    in_flight: Mutex<HashMap<String, HashSet<u64>>>,
+    /// Notifies the delay executor the module processed all its queued wakeups
+    idle_sender: UnboundedSender<String>,
+}
+
+impl Clock {
+    pub fn real() -> Clock {
+        Clock {
+            origin: Instant::now(),
+            virtual_time: None,
+        }
+    }
+
+    /// Create a virtual clock, returning the receiver of the modules idle notifications
+    pub fn virtual_time() -> (Clock, UnboundedReceiver<String>) {
+        let (idle_sender, idle_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let clock = Clock {
+            origin: Instant::now(),
+            virtual_time: Some(Arc::new(VirtualTime {
+                now: Mutex::new(HashMap::new()),
+                in_flight: Mutex::new(HashMap::new()),
+                idle_sender,
+            })),
+        };
+        (clock, idle_receiver)
+    }
+
+    /// Time elapsed since the origin of the clock, as seen by the module
+    pub fn now(&self, controller_name: &str) -> Duration {
+        match &self.virtual_time {
+            Some(virtual_time) => virtual_time
+                .now
+                .lock()
+                .unwrap()
+                .get(controller_name)
+                .copied()
+                .unwrap_or_default(),
+            None => self.origin.elapsed(),
+        }
+    }
+
+    /// Move the virtual clock of the module forward to `to`. It never goes backward
+    pub fn advance(&self, controller_name: &str, to: Duration) {
+        if let Some(virtual_time) = &self.virtual_time {
+            let mut now = virtual_time.now.lock().unwrap();
+            let module_now = now.entry(controller_name.to_string()).or_default();
+            *module_now = (*module_now).max(to);
+        }
+    }
+
+    /// Track the host request as in flight: the module is not idle until its result arrives or it's cancelled
+    pub fn request_started(&self, controller_name: &str, async_request_id: u64) {
+        if let Some(virtual_time) = &self.virtual_time {
+            virtual_time
+                .in_flight
+                .lock()
+                .unwrap()
+                .entry(controller_name.to_string())
+                .or_default()
+                .insert(async_request_id);
+        }
+    }
+
+    /// Stop tracking the host request, either because its first result arrived or because it was cancelled.
+    /// Ids not tracked, like the ones of delays and watches, are ignored
+    pub fn request_finished(&self, controller_name: &str, async_request_id: u64) {
+        if let Some(virtual_time) = &self.virtual_time {
+            if let Some(in_flight) = virtual_time.in_flight.lock().unwrap().get_mut(controller_name) {
+                in_flight.remove(&async_request_id);
+            }
+        }
+    }
+
+    /// Stop tracking all the host requests of the module
+    pub fn requests_cancelled(&self, controller_name: &str) {
+        if let Some(virtual_time) = &self.virtual_time {
+            virtual_time.in_flight.lock().unwrap().remove(controller_name);
+        }
+    }
+
+    /// Notify the module processed all its queued wakeups, so its next delay can fire in virtual time.
+    /// Nothing is sent while the module waits for host requests: their results will wake it up again
+    pub fn notify_idle(&self, controller_name: &str) {
+        if let Some(virtual_time) = &self.virtual_time {
+            let waiting = virtual_time
+                .in_flight
+                .lock()
+                .unwrap()
+                .get(controller_name)
+                .map_or(false, |in_flight| !in_flight.is_empty());
+            if !waiting {
+                let _ = virtual_time.idle_sender.send(controller_name.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn module_waiting_for_requests_is_not_idle() {
+        let (clock, mut idle_rx) = Clock::virtual_time();
+        clock.request_started("a", 1);
+        clock.request_started("a", 2);
+
+        clock.notify_idle("a");
+        assert!(idle_rx.try_recv().is_err());
+
+        clock.request_finished("a", 1);
+        clock.notify_idle("a");
+        assert!(idle_rx.try_recv().is_err());
+
+        clock.request_finished("a", 2);
+        clock.notify_idle("a");
+        assert_eq!(idle_rx.try_recv().unwrap(), "a");
+    }
+
+    #[test]
+    fn cancelled_requests_are_not_waited_for() {
+        let (clock, mut idle_rx) = Clock::virtual_time();
+        clock.request_started("a", 1);
+        clock.request_started("b", 2);
+        clock.requests_cancelled("a");
+
+        clock.notify_idle("a");
+        clock.notify_idle("b");
+        assert_eq!(idle_rx.try_recv().unwrap(), "a");
+        assert!(idle_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn virtual_clock_never_goes_backward() {
+        let (clock, _idle_rx) = Clock::virtual_time();
+        clock.advance("a", Duration::from_secs(5));
+        clock.advance("a", Duration::from_secs(1));
+        assert_eq!(clock.now("a"), Duration::from_secs(5));
+        assert_eq!(clock.now("b"), Duration::from_secs(0));
+    }
+}
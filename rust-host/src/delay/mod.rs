@@ -1,30 +1,271 @@
 use tokio::sync::mpsc::{Sender, UnboundedReceiver};
-use crate::abi::commands::AbiCommand;
+use crate::abi::commands::ExecutorCommand;
 use crate::abi::dispatcher::{AsyncType, AsyncResult};
-use std::convert::{TryFrom, TryInto};
+use std::collections::{BTreeSet, HashMap};
+use tokio::stream::StreamExt;
+use tokio::time::{delay_queue, DelayQueue};
 
-use futures::{StreamExt};
 use std::time::Duration;
 
+mod clock;
+
+pub use clock::Clock;
+
 pub async fn start_delay_executor(
-    rx: UnboundedReceiver<AbiCommand<Duration>>,
+    rx: UnboundedReceiver<ExecutorCommand<Duration>>,
     tx: Sender<AsyncResult>,
+    clock: Clock,
+    idle_rx: Option<UnboundedReceiver<String>>,
 ) -> anyhow::Result<()> {
-    rx.for_each_concurrent(10, |delay_command| async {
-        debug!(
-            "Received delay command from '{}' with id {}: {:?}",
-            &delay_command.controller_name, &delay_command.async_request_id, delay_command.value
-        );
-
-        tokio::time::delay_for(delay_command.value.into()).await;
-
-        tx.clone().send(AsyncResult {
-            async_request_id: delay_command.async_request_id,
-            controller_name: delay_command.controller_name,
-            value: None,
-            async_type: AsyncType::Future
-        }).await.expect("Send error");
-
-    }).await;
+    match idle_rx {
+        Some(idle_rx) => run_virtual_delays(rx, idle_rx, tx, clock).await,
+        None => run_delays(rx, tx).await,
+    }
+}
+
+/// Serve the delays with a single timer wheel, where the timers are cancelled as soon as the module drops them
+async fn run_delays(mut rx: UnboundedReceiver<ExecutorCommand<Duration>>, mut tx: Sender<AsyncResult>) -> anyhow::Result<()> {
+    let mut timers: DelayQueue<(String, u64)> = DelayQueue::new();
+    let mut keys: HashMap<(String, u64), delay_queue::Key> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            command = rx.recv() => match command {
+                Some(ExecutorCommand::Start(delay_command)) => {
+                    debug!(
+                        "Received delay command from '{}' with id {}: {:?}",
+                        &delay_command.controller_name, &delay_command.async_request_id, delay_command.value
+                    );
+                    let timer = (delay_command.controller_name, delay_command.async_request_id);
+                    keys.insert(timer.clone(), timers.insert(timer, delay_command.value));
+                }
+                Some(ExecutorCommand::Deny { command, .. }) => {
+                    // Delays don't touch any resource, just complete it
+                    let timer = (command.controller_name, command.async_request_id);
+                    keys.insert(timer.clone(), timers.insert(timer, Duration::from_millis(0)));
+                }
+                Some(ExecutorCommand::Cancel { controller_name, async_request_id }) => {
+                    if let Some(key) = keys.remove(&(controller_name, async_request_id)) {
+                        timers.remove(&key);
+                    }
+                }
+                Some(ExecutorCommand::CancelAll { controller_name }) => {
+                    let cancelled: Vec<(String, u64)> = keys
+                        .keys()
+                        .filter(|(name, _)| name == &controller_name)
+                        .cloned()
+                        .collect();
+                    for timer in cancelled {
+                        if let Some(key) = keys.remove(&timer) {
+                            timers.remove(&key);
+                        }
+                    }
+                }
+                None => break,
+            },
+            Some(expired) = timers.next() => {
+                let (controller_name, async_request_id) = match expired {
+                    Ok(expired) => expired.into_inner(),
+                    Err(e) => {
+                        error!("Timer wheel failure: {}", e);
+                        continue;
+                    }
+                };
+                keys.remove(&(controller_name.clone(), async_request_id));
+
+                tx.send(AsyncResult {
+                    async_request_id,
+                    controller_name,
+                    value: None,
+                    async_type: AsyncType::Future
+                }).await?;
+            }
+        }
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Delays of a module in virtual time as `(deadline, async_request_id)`, ordered by deadline and then by registration
+type VirtualDelays = BTreeSet<(Duration, u64)>;
+
+/// Serve the delays in virtual time: when a module is idle, its earliest delay fires right away
+/// and its clock jumps to the delay deadline
+async fn run_virtual_delays(
+    mut rx: UnboundedReceiver<ExecutorCommand<Duration>>,
+    mut idle_rx: UnboundedReceiver<String>,
+    mut tx: Sender<AsyncResult>,
+    clock: Clock,
+) -> anyhow::Result<()> {
+    let mut delays: HashMap<String, VirtualDelays> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            command = rx.recv() => match command {
+                Some(command) => apply_virtual_command(&mut delays, &clock, command),
+                None => break,
+            },
+            Some(controller_name) = idle_rx.recv() => {
+                // The module sent its commands before going idle, so apply them first
+                while let Ok(command) = rx.try_recv() {
+                    apply_virtual_command(&mut delays, &clock, command);
+                }
+
+                let next = delays
+                    .get_mut(&controller_name)
+                    .and_then(|module_delays| {
+                        let next = module_delays.iter().next().copied()?;
+                        module_delays.remove(&next);
+                        Some(next)
+                    });
+                if let Some((deadline, async_request_id)) = next {
+                    debug!("Firing delay {} of '{}' at virtual time {:?}", async_request_id, &controller_name, deadline);
+                    clock.advance(&controller_name, deadline);
+                    tx.send(AsyncResult {
+                        async_request_id,
+                        controller_name,
+                        value: None,
+                        async_type: AsyncType::Future
+                    }).await?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn apply_virtual_command(delays: &mut HashMap<String, VirtualDelays>, clock: &Clock, command: ExecutorCommand<Duration>) {
+    match command {
+        ExecutorCommand::Start(delay_command) => {
+            let deadline = clock.now(&delay_command.controller_name) + delay_command.value;
+            delays
+                .entry(delay_command.controller_name)
+                .or_default()
+                .insert((deadline, delay_command.async_request_id));
+        }
+        ExecutorCommand::Deny { command, .. } => {
+            let now = clock.now(&command.controller_name);
+            delays
+                .entry(command.controller_name)
+                .or_default()
+                .insert((now, command.async_request_id));
+        }
+        ExecutorCommand::Cancel { controller_name, async_request_id } => {
+            if let Some(module_delays) = delays.get_mut(&controller_name) {
+                let cancelled = module_delays.iter().find(|(_, id)| *id == async_request_id).copied();
+                if let Some(cancelled) = cancelled {
+                    module_delays.remove(&cancelled);
+                }
+            }
+        }
+        ExecutorCommand::CancelAll { controller_name } => {
+            delays.remove(&controller_name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abi::commands::AbiCommand;
+
+    fn start(controller_name: &str, async_request_id: u64, secs: u64) -> ExecutorCommand<Duration> {
+        ExecutorCommand::Start(AbiCommand {
+            async_request_id,
+            controller_name: controller_name.to_string(),
+            value: Duration::from_secs(secs),
+        })
+    }
+
+    fn cancel(controller_name: &str, async_request_id: u64) -> ExecutorCommand<Duration> {
+        ExecutorCommand::Cancel { controller_name: controller_name.to_string(), async_request_id }
+    }
+
+    #[test]
+    fn virtual_delays_are_ordered_by_deadline_then_registration() {
+        let (clock, _idle_rx) = Clock::virtual_time();
+        let mut delays = HashMap::new();
+        apply_virtual_command(&mut delays, &clock, start("a", 1, 3));
+        apply_virtual_command(&mut delays, &clock, start("a", 2, 1));
+        apply_virtual_command(&mut delays, &clock, start("a", 3, 1));
+
+        let order: Vec<u64> = delays["a"].iter().map(|(_, id)| *id).collect();
+        assert_eq!(order, vec![2, 3, 1]);
+    }
+
+    #[test]
+    fn virtual_delays_start_from_the_module_clock() {
+        let (clock, _idle_rx) = Clock::virtual_time();
+        clock.advance("a", Duration::from_secs(10));
+        let mut delays = HashMap::new();
+        apply_virtual_command(&mut delays, &clock, start("a", 1, 5));
+        apply_virtual_command(&mut delays, &clock, start("b", 2, 5));
+
+        assert!(delays["a"].contains(&(Duration::from_secs(15), 1)));
+        assert!(delays["b"].contains(&(Duration::from_secs(5), 2)));
+    }
+
+    #[test]
+    fn cancel_removes_only_the_virtual_delay() {
+        let (clock, _idle_rx) = Clock::virtual_time();
+        let mut delays = HashMap::new();
+        apply_virtual_command(&mut delays, &clock, start("a", 1, 1));
+        apply_virtual_command(&mut delays, &clock, start("a", 2, 2));
+        apply_virtual_command(&mut delays, &clock, cancel("a", 1));
+        apply_virtual_command(&mut delays, &clock, cancel("b", 2));
+
+        let ids: Vec<u64> = delays["a"].iter().map(|(_, id)| *id).collect();
+        assert_eq!(ids, vec![2]);
+    }
+
+    #[test]
+    fn cancel_all_removes_only_the_module_virtual_delays() {
+        let (clock, _idle_rx) = Clock::virtual_time();
+        let mut delays = HashMap::new();
+        apply_virtual_command(&mut delays, &clock, start("a", 1, 1));
+        apply_virtual_command(&mut delays, &clock, start("b", 2, 1));
+        apply_virtual_command(&mut delays, &clock, ExecutorCommand::CancelAll { controller_name: "a".to_string() });
+
+        assert!(!delays.contains_key("a"));
+        assert_eq!(delays["b"].len(), 1);
+    }
+
+    #[tokio::test]
+    async fn idle_module_fires_its_earliest_virtual_delay() {
+        let (clock, idle_rx) = Clock::virtual_time();
+        let (command_tx, command_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (result_tx, mut result_rx) = tokio::sync::mpsc::channel(10);
+        tokio::spawn(run_virtual_delays(command_rx, idle_rx, result_tx, clock.clone()));
+
+        command_tx.send(start("a", 1, 5)).unwrap();
+        command_tx.send(start("a", 2, 1)).unwrap();
+        command_tx.send(start("a", 3, 3)).unwrap();
+        command_tx.send(cancel("a", 3)).unwrap();
+
+        clock.notify_idle("a");
+        assert_eq!(result_rx.recv().await.unwrap().async_request_id, 2);
+        assert_eq!(clock.now("a"), Duration::from_secs(1));
+
+        clock.notify_idle("a");
+        assert_eq!(result_rx.recv().await.unwrap().async_request_id, 1);
+        assert_eq!(clock.now("a"), Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn cancel_all_stops_the_virtual_delays_of_the_module() {
+        let (clock, idle_rx) = Clock::virtual_time();
+        let (command_tx, command_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (result_tx, mut result_rx) = tokio::sync::mpsc::channel(10);
+        tokio::spawn(run_virtual_delays(command_rx, idle_rx, result_tx, clock.clone()));
+
+        command_tx.send(start("a", 1, 1)).unwrap();
+        command_tx.send(start("b", 2, 2)).unwrap();
+        command_tx.send(ExecutorCommand::CancelAll { controller_name: "a".to_string() }).unwrap();
+
+        // The idle notifications are served in order, so the first result is the one of "b"
+        clock.notify_idle("a");
+        clock.notify_idle("b");
+        let fired = result_rx.recv().await.unwrap();
+        assert_eq!((fired.controller_name.as_str(), fired.async_request_id), ("b", 2));
+        assert_eq!(clock.now("a"), Duration::from_secs(0));
+    }
+}
@@ -1,60 +1,347 @@
-use tokio::sync::mpsc::{Sender, UnboundedReceiver};
-use crate::abi::commands::AbiCommand;
-use crate::abi::dispatcher::{AsyncType, AsyncResult};
+use tokio::sync::mpsc::{Sender, UnboundedReceiver, UnboundedSender};
+use crate::abi::commands::{AbiCommand, ExecutorCommand};
+use crate::abi::dispatcher::{AsyncType, AsyncResult, AsyncValue};
+use crate::abi::tasks::InFlightTasks;
 use std::convert::{TryFrom, TryInto};
+use std::future::Future;
+use std::time::{Duration, SystemTime};
+use tokio::time::Instant;
 use http::HeaderMap;
 
-use crate::abi::rust_v1alpha1::HttpResponse;
-use futures::{StreamExt};
+use crate::modules::{forbidden_status, RateLimit};
+use kube_abi_types::v1alpha1::HttpError;
+
+mod scheduler;
+
+use scheduler::FairScheduler;
+
+/// Request executed on behalf of a module
+#[derive(Debug)]
+pub struct HttpCommand {
+    pub request: http::Request<Vec<u8>>,
+    /// If `true`, the response is sent to the module as a stream: first the response without the body,
+    /// then the body chunks as they arrive
+    pub streaming: bool,
+    /// Timeout set by the module, replacing the default one of the executor
+    pub timeout: Option<Duration>,
+    /// Rate limit of the module sending the request
+    pub rate_limit: Option<RateLimit>,
+    /// Set when the request was throttled by the API server and is queued again to be retried
+    pub retry: Option<ThrottledRetry>,
+}
+
+/// Retry of a request the API server answered with `429 Too Many Requests`
+#[derive(Debug, Clone, Copy)]
+pub struct ThrottledRetry {
+    /// Number of retries of the request, this one included
+    pub attempt: usize,
+    /// The retry is not sent before this instant, as asked by the `Retry-After` header
+    pub not_before: Instant,
+    /// Deadline of the first attempt, which the retries don't extend
+    pub deadline: Instant,
+}
+
+/// Timeout of the requests which don't specify one
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Maximum number of requests sent concurrently to the API server, shared by all the modules
+const MAX_IN_FLIGHT: usize = 10;
+
+/// Maximum number of retries of a request throttled by the API server
+const MAX_THROTTLED_RETRIES: usize = 3;
 
 pub async fn start_request_executor(
-    rx: UnboundedReceiver<AbiCommand<http::Request<Vec<u8>>>>,
+    mut rx: UnboundedReceiver<ExecutorCommand<HttpCommand>>,
     tx: Sender<AsyncResult>,
     cluster_url: url::Url,
     http_client: reqwest::Client,
+    default_timeout: Duration,
 ) -> anyhow::Result<()> {
-    let cluster_url = cluster_url.as_str();
-    rx.for_each_concurrent(10, |mut http_command| async {
-        // Patch the request URI
-        *http_command.value.uri_mut() = http::Uri::try_from(
-            generate_url(cluster_url, http_command.value.uri().path_and_query().unwrap())
-        ).expect("Cannot build the final uri");
-
-        debug!(
-            "Received request command from '{}' with id {}: {} {}",
-            &http_command.controller_name, &http_command.async_request_id, http_command.value.method().as_str() ,http_command.value.uri()
-        );
-
-        // Execute the request
-        let response = http_client.clone().execute(http_command.value.try_into().unwrap()).await
-            .expect("Successful response");
-
-        // Serialize the response
-        let status_code = response.status();
-        let mut headers = HeaderMap::with_capacity(response.headers().len());
-        for (k, v) in response.headers().iter() {
-            headers.append(k, v.clone());
+    let in_flight = InFlightTasks::default();
+    let mut scheduler = FairScheduler::new(MAX_IN_FLIGHT);
+    let (completed_tx, mut completed_rx) = tokio::sync::mpsc::unbounded_channel();
+    let (retry_tx, mut retry_rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut throttled_until: Option<Instant> = None;
+
+    loop {
+        // Wake up when the first throttled controller can send again
+        let throttle_delay = async move {
+            match throttled_until {
+                Some(instant) => tokio::time::delay_until(instant).await,
+                None => futures::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            command = rx.recv() => match command {
+                Some(ExecutorCommand::Start(http_command)) => scheduler.enqueue(http_command),
+                Some(ExecutorCommand::Deny { command, message }) => {
+                    tokio::spawn(deny_request(command, message, tx.clone()));
+                }
+                Some(ExecutorCommand::Cancel { controller_name, async_request_id }) => {
+                    if !scheduler.remove(&controller_name, async_request_id) {
+                        in_flight.abort(&controller_name, async_request_id)
+                    }
+                }
+                Some(ExecutorCommand::CancelAll { controller_name }) => {
+                    scheduler.remove_all(&controller_name);
+                    in_flight.abort_all(&controller_name)
+                }
+                None => break,
+            },
+            Some(()) = completed_rx.recv() => scheduler.completed(),
+            Some(http_command) = retry_rx.recv() => scheduler.requeue(http_command),
+            _ = throttle_delay => {}
         }
-        let response_body = response.bytes().await
-            .expect("Bytes");
-
-        let inner_response = HttpResponse {
-            status_code,
-            headers,
-            body: response_body.to_vec(),
-        }; //TODO Design problem here: i'm using an abi version specific type here. Needs some engineering
-
-        tx.clone().send(AsyncResult {
-            controller_name: http_command.controller_name,
-            async_request_id: http_command.async_request_id,
-            async_type: AsyncType::Future,
-            value: Some(bincode::serialize(&inner_response).expect("Error while serializing"))
-        }).await.expect("Send error");
-    }).await;
+
+        throttled_until = loop {
+            match scheduler.next(Instant::now()) {
+                Ok(http_command) => {
+                    let (controller_name, async_request_id) =
+                        (http_command.controller_name.clone(), http_command.async_request_id);
+                    let completed = CompletionGuard(completed_tx.clone());
+                    in_flight.spawn(
+                        controller_name,
+                        async_request_id,
+                        execute_request(
+                            http_command,
+                            tx.clone(),
+                            cluster_url.clone(),
+                            http_client.clone(),
+                            default_timeout,
+                            completed,
+                            retry_tx.clone(),
+                        ),
+                    );
+                }
+                Err(throttled_until) => break throttled_until,
+            }
+        };
+    }
 
     Ok(())
 }
 
+/// Notifies the scheduler when the request completes, even if its task is aborted.
+/// Streamed requests complete as soon as the response head is received, so long running streams
+/// like watches and logs don't hold the concurrency limit
+struct CompletionGuard(UnboundedSender<()>);
+
+impl Drop for CompletionGuard {
+    fn drop(&mut self) {
+        let _ = self.0.send(());
+    }
+}
+
+async fn deny_request(
+    http_command: AbiCommand<HttpCommand>,
+    message: String,
+    mut tx: Sender<AsyncResult>,
+) {
+    let mut headers = HeaderMap::new();
+    headers.insert(http::header::CONTENT_TYPE, http::HeaderValue::from_static("application/json"));
+    let body = serde_json::to_vec(&forbidden_status(&message)).expect("Error while serializing");
+
+    if http_command.value.streaming {
+        let head = build_response(http::StatusCode::FORBIDDEN, headers, Vec::new());
+        send_stream_item(&mut tx, &http_command, Some(AsyncValue::HttpResponse(Ok(head)))).await;
+        send_stream_item(&mut tx, &http_command, Some(AsyncValue::HttpBodyChunk(Ok(body)))).await;
+        send_stream_item(&mut tx, &http_command, None).await;
+        return;
+    }
+
+    send_result(&mut tx, &http_command, Ok(build_response(http::StatusCode::FORBIDDEN, headers, body))).await;
+}
+
+async fn execute_request(
+    mut http_command: AbiCommand<HttpCommand>,
+    mut tx: Sender<AsyncResult>,
+    cluster_url: url::Url,
+    http_client: reqwest::Client,
+    default_timeout: Duration,
+    completed: CompletionGuard,
+    retry_tx: UnboundedSender<AbiCommand<HttpCommand>>,
+) {
+    let request = std::mem::replace(&mut http_command.value.request, http::Request::new(Vec::new()));
+    debug!(
+        "Received request command from '{}' with id {}: {} {}",
+        &http_command.controller_name, &http_command.async_request_id, request.method().as_str(), request.uri()
+    );
+
+    // The deadline covers the whole response for plain requests, and only the response head for streams.
+    // Retries keep the deadline of the first attempt
+    let timeout = http_command.value.timeout.unwrap_or(default_timeout);
+    let deadline = http_command.value.retry.map_or_else(|| Instant::now() + timeout, |retry| retry.deadline);
+
+    let response = with_deadline(deadline, timeout, send_request(clone_request(&request), &cluster_url, &http_client)).await;
+
+    // When the API server asks to, queue the request again instead of waiting here, so the request
+    // doesn't hold a concurrency slot while waiting and the retry goes through the module rate limit
+    if let Ok(r) = &response {
+        if let Some(retry) = throttled_retry(r, http_command.value.retry, deadline) {
+            debug!(
+                "Request {} of '{}' throttled by the API server, retrying at {:?}",
+                &http_command.async_request_id, &http_command.controller_name, retry.not_before
+            );
+            http_command.value.request = request;
+            http_command.value.retry = Some(retry);
+            let _ = retry_tx.send(http_command);
+            return;
+        }
+    }
+
+    let response = match response {
+        Ok(response) => response,
+        Err(e) => {
+            warn!("Request {} of '{}' failed: {}", &http_command.async_request_id, &http_command.controller_name, &e);
+            if http_command.value.streaming {
+                send_stream_item(&mut tx, &http_command, Some(AsyncValue::HttpResponse(Err(e)))).await;
+                send_stream_item(&mut tx, &http_command, None).await;
+            } else {
+                send_result(&mut tx, &http_command, Err(e)).await;
+            }
+            return;
+        }
+    };
+
+    let status_code = response.status();
+    let mut headers = HeaderMap::with_capacity(response.headers().len());
+    for (k, v) in response.headers().iter() {
+        headers.append(k, v.clone());
+    }
+
+    if http_command.value.streaming {
+        drop(completed);
+        stream_response(&http_command, status_code, headers, response, &mut tx).await;
+        return;
+    }
+
+    let body = async { response.bytes().await.map_err(|e| HttpError::Transport(e.to_string())) };
+    let result = with_deadline(deadline, timeout, body).await
+        .map(|response_body| build_response(status_code, headers, response_body.to_vec()));
+
+    send_result(&mut tx, &http_command, result).await;
+}
+
+/// Fail with [`HttpError::Timeout`] if the provided future doesn't complete before the deadline
+async fn with_deadline<T, F>(deadline: Instant, timeout: Duration, fut: F) -> Result<T, HttpError>
+where
+    F: Future<Output = Result<T, HttpError>>,
+{
+    tokio::time::timeout_at(deadline, fut)
+        .await
+        .unwrap_or(Err(HttpError::Timeout(timeout)))
+}
+
+/// The retry of the request, if the API server throttled it and the retry can complete before the deadline
+fn throttled_retry(response: &reqwest::Response, previous: Option<ThrottledRetry>, deadline: Instant) -> Option<ThrottledRetry> {
+    if response.status() != http::StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    let attempt = previous.map_or(0, |retry| retry.attempt) + 1;
+    if attempt > MAX_THROTTLED_RETRIES {
+        return None;
+    }
+    let not_before = Instant::now() + retry_after(response.headers(), SystemTime::now())?;
+    if not_before >= deadline {
+        return None;
+    }
+    Some(ThrottledRetry { attempt, not_before, deadline })
+}
+
+/// The delay requested by the `Retry-After` header, either in seconds or as an HTTP date
+fn retry_after(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+    let value = headers.get(http::header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    // A date in the past asks to retry right away
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+fn clone_request(request: &http::Request<Vec<u8>>) -> http::Request<Vec<u8>> {
+    let mut cloned = http::Request::new(request.body().clone());
+    *cloned.method_mut() = request.method().clone();
+    *cloned.uri_mut() = request.uri().clone();
+    *cloned.headers_mut() = request.headers().clone();
+    cloned
+}
+
+/// Send the request to the API server, returning the response as soon as the headers are received
+async fn send_request(
+    mut request: http::Request<Vec<u8>>,
+    cluster_url: &url::Url,
+    http_client: &reqwest::Client,
+) -> Result<reqwest::Response, HttpError> {
+    // Patch the request URI
+    let path_and_query = request.uri()
+        .path_and_query()
+        .ok_or_else(|| HttpError::InvalidRequest(format!("missing path in uri '{}'", request.uri())))?;
+    *request.uri_mut() = http::Uri::try_from(generate_url(cluster_url.as_str(), path_and_query))
+        .map_err(|e| HttpError::InvalidRequest(e.to_string()))?;
+
+    let request: reqwest::Request = request.try_into()
+        .map_err(|e: reqwest::Error| HttpError::InvalidRequest(e.to_string()))?;
+
+    http_client.execute(request).await
+        .map_err(|e| HttpError::Transport(e.to_string()))
+}
+
+/// Send the response without the body, then the body chunks as they arrive
+async fn stream_response(
+    http_command: &AbiCommand<HttpCommand>,
+    status_code: http::StatusCode,
+    headers: HeaderMap,
+    mut response: reqwest::Response,
+    tx: &mut Sender<AsyncResult>,
+) {
+    let head = build_response(status_code, headers, Vec::new());
+    send_stream_item(tx, http_command, Some(AsyncValue::HttpResponse(Ok(head)))).await;
+    loop {
+        match response.chunk().await {
+            Ok(Some(chunk)) => send_stream_item(tx, http_command, Some(AsyncValue::HttpBodyChunk(Ok(chunk.to_vec())))).await,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Error while streaming the response of request {}: {}", &http_command.async_request_id, e);
+                let error = AsyncValue::HttpBodyChunk(Err(HttpError::Transport(e.to_string())));
+                send_stream_item(tx, http_command, Some(error)).await;
+                break;
+            }
+        }
+    }
+    send_stream_item(tx, http_command, None).await;
+}
+
+pub(crate) fn build_response(status_code: http::StatusCode, headers: HeaderMap, body: Vec<u8>) -> http::Response<Vec<u8>> {
+    let mut response = http::Response::new(body);
+    *response.status_mut() = status_code;
+    *response.headers_mut() = headers;
+    response
+}
+
+async fn send_result(
+    tx: &mut Sender<AsyncResult>,
+    http_command: &AbiCommand<HttpCommand>,
+    result: Result<http::Response<Vec<u8>>, HttpError>,
+) {
+    let _ = tx.send(AsyncResult {
+        controller_name: http_command.controller_name.clone(),
+        async_request_id: http_command.async_request_id,
+        async_type: AsyncType::Future,
+        value: Some(AsyncValue::HttpResponse(result)),
+    }).await;
+}
+
+async fn send_stream_item(tx: &mut Sender<AsyncResult>, http_command: &AbiCommand<HttpCommand>, value: Option<AsyncValue>) {
+    let _ = tx.send(AsyncResult {
+        controller_name: http_command.controller_name.clone(),
+        async_request_id: http_command.async_request_id,
+        async_type: AsyncType::Stream,
+        value,
+    }).await;
+}
+
 /// An internal url joiner to deal with the two different interfaces
 ///
 /// - api module produces a http::Uri which we can turn into a PathAndQuery (has a leading slash by construction)
@@ -64,4 +351,41 @@ pub async fn start_request_executor(
 fn generate_url(cluster_url: &str, request_p_and_q: &http::uri::PathAndQuery) -> String {
     let base = cluster_url.trim_end_matches('/');
     format!("{}{}", base, request_p_and_q)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn retry_after_header(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(http::header::RETRY_AFTER, http::HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn retry_after_in_seconds() {
+        let now = SystemTime::now();
+        assert_eq!(retry_after(&retry_after_header("3"), now), Some(Duration::from_secs(3)));
+        assert_eq!(retry_after(&retry_after_header(" 0 "), now), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn retry_after_as_http_date() {
+        let now = httpdate::parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        let headers = retry_after_header("Sun, 06 Nov 1994 08:49:47 GMT");
+        assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(10)));
+
+        // Dates in the past don't delay the retry
+        let headers = retry_after_header("Sun, 06 Nov 1994 08:49:27 GMT");
+        assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn retry_after_invalid_or_missing() {
+        let now = SystemTime::now();
+        assert_eq!(retry_after(&retry_after_header("soon"), now), None);
+        assert_eq!(retry_after(&retry_after_header("-1"), now), None);
+        assert_eq!(retry_after(&HeaderMap::new(), now), None);
+    }
+}
@@ -0,0 +1,349 @@
+use super::HttpCommand;
+use crate::abi::commands::AbiCommand;
+use crate::modules::RateLimit;
+use std::collections::{HashMap, VecDeque};
+use tokio::time::Instant;
+
+/// Queues the requests of each controller, handing them out in round robin
+/// across the controllers, within the concurrency limit and the controllers rate limits.
+pub struct FairScheduler {
+    max_in_flight: usize,
+    in_flight: usize,
+    queues: HashMap<String, ControllerQueue>,
+    /// Controllers with queued requests, in the order they will be served
+    ready: VecDeque<String>,
+}
+
+struct ControllerQueue {
+    requests: VecDeque<AbiCommand<HttpCommand>>,
+    bucket: TokenBucket,
+}
+
+impl FairScheduler {
+    pub fn new(max_in_flight: usize) -> FairScheduler {
+        FairScheduler {
+            max_in_flight,
+            in_flight: 0,
+            queues: HashMap::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    pub fn enqueue(&mut self, command: AbiCommand<HttpCommand>) {
+        self.queue(command, false)
+    }
+
+    /// Queue again a request throttled by the API server, before the other requests of its controller:
+    /// the controller sends nothing until the retry is due, and the retry takes a token like any other request
+    pub fn requeue(&mut self, command: AbiCommand<HttpCommand>) {
+        self.queue(command, true)
+    }
+
+    fn queue(&mut self, command: AbiCommand<HttpCommand>, front: bool) {
+        let rate_limit = command.value.rate_limit;
+        let queue = self
+            .queues
+            .entry(command.controller_name.clone())
+            .or_insert_with(|| ControllerQueue {
+                requests: VecDeque::new(),
+                bucket: TokenBucket::new(rate_limit),
+            });
+        // The module was reloaded with a different rate limit
+        if queue.bucket.rate_limit != rate_limit {
+            queue.bucket = TokenBucket::new(rate_limit);
+        }
+
+        if queue.requests.is_empty() {
+            self.ready.push_back(command.controller_name.clone());
+        }
+        if front {
+            queue.requests.push_front(command);
+        } else {
+            queue.requests.push_back(command);
+        }
+    }
+
+    /// Remove the queued request, returning `false` if it's not queued
+    pub fn remove(&mut self, controller_name: &str, async_request_id: u64) -> bool {
+        let queue = match self.queues.get_mut(controller_name) {
+            Some(queue) => queue,
+            None => return false,
+        };
+        let len = queue.requests.len();
+        queue.requests.retain(|command| command.async_request_id != async_request_id);
+        if queue.requests.is_empty() {
+            self.ready.retain(|name| name != controller_name);
+        }
+        queue.requests.len() != len
+    }
+
+    /// Remove all the queued requests and the rate limit state of the controller
+    pub fn remove_all(&mut self, controller_name: &str) {
+        self.queues.remove(controller_name);
+        self.ready.retain(|name| name != controller_name);
+    }
+
+    /// Notify that a request handed out by [`FairScheduler::next`] is completed
+    pub fn completed(&mut self) {
+        self.in_flight -= 1;
+    }
+
+    /// Hand out the next request to execute, if any. When requests are queued but all
+    /// their controllers are throttled, it returns the instant the first one can be sent.
+    pub fn next(&mut self, now: Instant) -> Result<AbiCommand<HttpCommand>, Option<Instant>> {
+        if self.in_flight >= self.max_in_flight {
+            return Err(None);
+        }
+
+        let mut throttled_until: Option<Instant> = None;
+        for _ in 0..self.ready.len() {
+            let controller_name = self.ready.pop_front().unwrap();
+            let queue = self.queues.get_mut(&controller_name).unwrap();
+
+            // A throttled retry waits for the instant the API server asked for
+            let not_before = queue.requests.front().and_then(|command| command.value.retry).map(|retry| retry.not_before);
+            let acquired = match not_before {
+                Some(not_before) if not_before > now => Err(not_before),
+                _ => queue.bucket.try_acquire(now),
+            };
+            match acquired {
+                Ok(()) => {
+                    let command = queue.requests.pop_front().unwrap();
+                    if !queue.requests.is_empty() {
+                        self.ready.push_back(controller_name);
+                    }
+                    self.in_flight += 1;
+                    return Ok(command);
+                }
+                Err(ready_at) => {
+                    throttled_until = Some(throttled_until.map_or(ready_at, |t| t.min(ready_at)));
+                    self.ready.push_back(controller_name);
+                }
+            }
+        }
+        Err(throttled_until)
+    }
+}
+
+/// Token bucket refilled at `qps` tokens per second, up to `burst` tokens
+struct TokenBucket {
+    rate_limit: Option<RateLimit>,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate_limit: Option<RateLimit>) -> TokenBucket {
+        TokenBucket {
+            rate_limit,
+            tokens: rate_limit.map_or(0.0, |rl| rl.burst.max(1) as f64),
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take a token, or return the instant the next token is available
+    fn try_acquire(&mut self, now: Instant) -> Result<(), Instant> {
+        let rate_limit = match self.rate_limit {
+            Some(rate_limit) if rate_limit.qps > 0.0 => rate_limit,
+            _ => return Ok(()),
+        };
+
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate_limit.qps).min(rate_limit.burst.max(1) as f64);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            let missing = 1.0 - self.tokens;
+            Err(now + std::time::Duration::from_secs_f64(missing / rate_limit.qps))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::ThrottledRetry;
+    use std::time::Duration;
+
+    fn command(controller_name: &str, async_request_id: u64, rate_limit: Option<RateLimit>) -> AbiCommand<HttpCommand> {
+        AbiCommand {
+            async_request_id,
+            controller_name: controller_name.to_string(),
+            value: HttpCommand {
+                request: http::Request::new(Vec::new()),
+                streaming: false,
+                timeout: None,
+                rate_limit,
+                retry: None,
+            },
+        }
+    }
+
+    fn next_id(scheduler: &mut FairScheduler, now: Instant) -> Option<(String, u64)> {
+        scheduler
+            .next(now)
+            .ok()
+            .map(|command| (command.controller_name, command.async_request_id))
+    }
+
+    #[test]
+    fn requests_are_served_in_round_robin_across_controllers() {
+        let mut scheduler = FairScheduler::new(10);
+        for id in 1..=3 {
+            scheduler.enqueue(command("a", id, None));
+        }
+        scheduler.enqueue(command("b", 4, None));
+        scheduler.enqueue(command("c", 5, None));
+
+        let now = Instant::now();
+        let order: Vec<u64> = std::iter::from_fn(|| next_id(&mut scheduler, now)).map(|(_, id)| id).collect();
+        assert_eq!(order, vec![1, 4, 5, 2, 3]);
+    }
+
+    #[test]
+    fn in_flight_requests_are_limited() {
+        let mut scheduler = FairScheduler::new(2);
+        for id in 1..=3 {
+            scheduler.enqueue(command("a", id, None));
+        }
+
+        let now = Instant::now();
+        assert_eq!(next_id(&mut scheduler, now), Some(("a".to_string(), 1)));
+        assert_eq!(next_id(&mut scheduler, now), Some(("a".to_string(), 2)));
+        assert!(matches!(scheduler.next(now), Err(None)));
+
+        scheduler.completed();
+        assert_eq!(next_id(&mut scheduler, now), Some(("a".to_string(), 3)));
+        assert!(matches!(scheduler.next(now), Err(None)));
+
+        // Nothing is queued anymore
+        scheduler.completed();
+        scheduler.completed();
+        assert!(matches!(scheduler.next(now), Err(None)));
+    }
+
+    #[test]
+    fn throttled_controllers_report_when_they_can_send_again() {
+        let rate_limit = Some(RateLimit { qps: 2.0, burst: 1 });
+        let mut scheduler = FairScheduler::new(10);
+        scheduler.enqueue(command("a", 1, rate_limit));
+        scheduler.enqueue(command("a", 2, rate_limit));
+
+        let now = Instant::now();
+        assert_eq!(next_id(&mut scheduler, now), Some(("a".to_string(), 1)));
+        let throttled_until = match scheduler.next(now) {
+            Err(Some(instant)) => instant,
+            _ => panic!("the second request should be throttled"),
+        };
+        assert_eq!(throttled_until, now + Duration::from_millis(500));
+        assert_eq!(next_id(&mut scheduler, throttled_until), Some(("a".to_string(), 2)));
+    }
+
+    #[test]
+    fn throttled_controllers_do_not_block_the_others() {
+        let mut scheduler = FairScheduler::new(10);
+        scheduler.enqueue(command("a", 1, Some(RateLimit { qps: 1.0, burst: 1 })));
+        scheduler.enqueue(command("a", 2, Some(RateLimit { qps: 1.0, burst: 1 })));
+        scheduler.enqueue(command("b", 3, None));
+        scheduler.enqueue(command("b", 4, None));
+
+        let now = Instant::now();
+        let order: Vec<u64> = std::iter::from_fn(|| next_id(&mut scheduler, now)).map(|(_, id)| id).collect();
+        assert_eq!(order, vec![1, 3, 4]);
+        assert!(matches!(scheduler.next(now), Err(Some(_))));
+    }
+
+    fn retry(command: &mut AbiCommand<HttpCommand>, not_before: Instant) {
+        command.value.retry = Some(ThrottledRetry {
+            attempt: 1,
+            not_before,
+            deadline: not_before + Duration::from_secs(30),
+        });
+    }
+
+    #[test]
+    fn retries_wait_for_retry_after_before_the_other_requests() {
+        let mut scheduler = FairScheduler::new(1);
+        let now = Instant::now();
+        scheduler.enqueue(command("a", 1, None));
+        scheduler.enqueue(command("a", 2, None));
+        scheduler.enqueue(command("b", 3, None));
+
+        // The first request is throttled by the API server and frees its slot
+        let mut throttled = scheduler.next(now).unwrap();
+        scheduler.completed();
+        let retry_at = now + Duration::from_secs(1);
+        retry(&mut throttled, retry_at);
+        scheduler.requeue(throttled);
+
+        // The other controllers are served meanwhile
+        assert_eq!(next_id(&mut scheduler, now), Some(("b".to_string(), 3)));
+        scheduler.completed();
+        assert!(matches!(scheduler.next(now), Err(Some(instant)) if instant == retry_at));
+
+        assert_eq!(next_id(&mut scheduler, retry_at), Some(("a".to_string(), 1)));
+        scheduler.completed();
+        assert_eq!(next_id(&mut scheduler, retry_at), Some(("a".to_string(), 2)));
+    }
+
+    #[test]
+    fn retries_take_a_token() {
+        let mut scheduler = FairScheduler::new(10);
+        let now = Instant::now();
+        scheduler.enqueue(command("a", 1, Some(RateLimit { qps: 1.0, burst: 1 })));
+
+        let mut throttled = scheduler.next(now).unwrap();
+        scheduler.completed();
+        retry(&mut throttled, now);
+        scheduler.requeue(throttled);
+
+        // The retry is due, but the bucket is empty
+        assert!(matches!(scheduler.next(now), Err(Some(instant)) if instant == now + Duration::from_secs(1)));
+        assert_eq!(next_id(&mut scheduler, now + Duration::from_secs(1)), Some(("a".to_string(), 1)));
+    }
+
+    #[test]
+    fn removed_requests_are_not_served() {
+        let mut scheduler = FairScheduler::new(10);
+        scheduler.enqueue(command("a", 1, None));
+        scheduler.enqueue(command("a", 2, None));
+        scheduler.enqueue(command("b", 3, None));
+
+        assert!(scheduler.remove("a", 1));
+        assert!(!scheduler.remove("a", 1));
+        scheduler.remove_all("b");
+
+        let now = Instant::now();
+        assert_eq!(next_id(&mut scheduler, now), Some(("a".to_string(), 2)));
+        assert!(matches!(scheduler.next(now), Err(None)));
+    }
+
+    #[test]
+    fn token_bucket_allows_bursts_then_refills_at_qps() {
+        let mut bucket = TokenBucket::new(Some(RateLimit { qps: 10.0, burst: 3 }));
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(bucket.try_acquire(now).is_ok());
+        }
+        assert_eq!(bucket.try_acquire(now), Err(now + Duration::from_millis(100)));
+
+        // The bucket refills up to the burst, not beyond
+        let later = now + Duration::from_secs(10);
+        for _ in 0..3 {
+            assert!(bucket.try_acquire(later).is_ok());
+        }
+        assert!(bucket.try_acquire(later).is_err());
+    }
+
+    #[test]
+    fn token_bucket_without_rate_limit_never_throttles() {
+        let mut bucket = TokenBucket::new(None);
+        let now = Instant::now();
+        for _ in 0..100 {
+            assert!(bucket.try_acquire(now).is_ok());
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use crate::kube_watch::WatchKey;
+use crate::modules::ModuleIdentity;
+use kube::api::ListParams;
+
+mod query;
+pub(crate) mod selector;
+pub(crate) use query::{reply, send_response, Objects};
+
+/// Query executed by a module against the shared cache of a resource,
+/// which is the store of the shared watch of the resource without selectors
+#[derive(Debug, Clone)]
+pub struct CacheQuery {
+    pub resource: kube::Resource,
+    pub verb: CacheVerb,
+    /// Identity of the module, each identity gets its own shared watch
+    pub identity: ModuleIdentity,
+}
+
+#[derive(Debug, Clone)]
+pub enum CacheVerb {
+    Get { name: String },
+    List { label_selector: Option<String>, field_selector: Option<String> },
+}
+
+impl CacheQuery {
+    /// Key of the shared watch whose store backs the cache queried
+    pub fn watch_key(&self) -> WatchKey {
+        WatchKey {
+            resource_version: String::new(),
+            resource: self.resource.clone(),
+            list_params: ListParams::default(),
+            identity: self.identity.clone(),
+        }
+        .normalized()
+    }
+
+    /// Build the request equivalent to this query, used to check it against the module policy
+    pub fn to_request(&self) -> Result<http::Request<Vec<u8>>, kube::Error> {
+        match &self.verb {
+            CacheVerb::Get { name } => self.resource.get(name),
+            CacheVerb::List { label_selector, field_selector } => self.resource.list(&ListParams {
+                label_selector: label_selector.clone(),
+                field_selector: field_selector.clone(),
+                ..ListParams::default()
+            }),
+        }
+    }
+}
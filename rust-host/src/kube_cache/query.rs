@@ -0,0 +1,76 @@
+use super::selector::Selector;
+use super::{CacheQuery, CacheVerb};
+use crate::abi::commands::AbiCommand;
+use crate::abi::dispatcher::{AsyncResult, AsyncType, AsyncValue};
+use crate::http::build_response;
+use crate::utils::failure_status;
+use http::{HeaderMap, StatusCode};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use tokio::sync::mpsc::Sender;
+
+/// Objects of a shared watch, keyed by namespace and name
+pub(crate) type Objects = BTreeMap<(String, String), Value>;
+
+/// Execute the query against the objects of the shared watch and send the result to the module
+pub(crate) async fn reply(
+    objects: &Objects,
+    resource_version: u64,
+    command: AbiCommand<CacheQuery>,
+    tx: Sender<AsyncResult>,
+) -> anyhow::Result<()> {
+    let resource = &command.value.resource;
+    let (status_code, body) = match &command.value.verb {
+        CacheVerb::Get { name } => {
+            let key = (resource.namespace.clone().unwrap_or_default(), name.clone());
+            match objects.get(&key) {
+                Some(obj) => (StatusCode::OK, obj.clone()),
+                None => (
+                    StatusCode::NOT_FOUND,
+                    failure_status(404, "NotFound", &format!("{} \"{}\" not found", &resource.kind, name)),
+                ),
+            }
+        }
+        CacheVerb::List { label_selector, field_selector } => match list_matching(objects, label_selector, field_selector) {
+            Ok(items) => (
+                StatusCode::OK,
+                serde_json::json!({
+                    "apiVersion": &resource.api_version,
+                    "kind": format!("{}List", &resource.kind),
+                    "metadata": { "resourceVersion": resource_version.to_string() },
+                    "items": items,
+                }),
+            ),
+            Err(message) => (StatusCode::BAD_REQUEST, failure_status(400, "BadRequest", &message)),
+        },
+    };
+
+    send_response(command, status_code, body, tx).await
+}
+
+fn list_matching(objects: &Objects, label_selector: &Option<String>, field_selector: &Option<String>) -> Result<Vec<Value>, String> {
+    let labels = label_selector.as_deref().map(Selector::parse_labels).transpose()?;
+    let fields = field_selector.as_deref().map(Selector::parse_fields).transpose()?;
+
+    Ok(objects
+        .values()
+        .filter(|obj| labels.as_ref().map_or(true, |s| s.matches_labels(obj)))
+        .filter(|obj| fields.as_ref().map_or(true, |s| s.matches_fields(obj)))
+        .cloned()
+        .collect())
+}
+
+pub(crate) async fn send_response(command: AbiCommand<CacheQuery>, status_code: StatusCode, body: Value, mut tx: Sender<AsyncResult>) -> anyhow::Result<()> {
+    let mut headers = HeaderMap::new();
+    headers.insert(http::header::CONTENT_TYPE, http::HeaderValue::from_static("application/json"));
+
+    let response = build_response(status_code, headers, serde_json::to_vec(&body)?);
+
+    tx.send(AsyncResult {
+        controller_name: command.controller_name,
+        async_request_id: command.async_request_id,
+        async_type: AsyncType::Future,
+        value: Some(AsyncValue::HttpResponse(Ok(response))),
+    }).await?;
+    Ok(())
+}
@@ -0,0 +1,264 @@
+use serde_json::Value;
+
+/// A parsed label or field selector, evaluated against the cached objects
+#[derive(Debug)]
+pub struct Selector {
+    requirements: Vec<Requirement>,
+}
+
+#[derive(Debug)]
+enum Requirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    In(String, Vec<String>),
+    NotIn(String, Vec<String>),
+    Exists(String),
+    NotExists(String),
+}
+
+impl Selector {
+    /// Parse a label selector, supporting the equality and the set based requirements
+    pub fn parse_labels(selector: &str) -> Result<Selector, String> {
+        let requirements = split_requirements(selector)?
+            .into_iter()
+            .map(parse_label_requirement)
+            .collect::<Result<Vec<Requirement>, String>>()?;
+        Ok(Selector { requirements })
+    }
+
+    /// Parse a field selector, supporting only the equality requirements
+    pub fn parse_fields(selector: &str) -> Result<Selector, String> {
+        let requirements = split_requirements(selector)?
+            .into_iter()
+            .map(|req| match parse_equality(req) {
+                Some(requirement) => Ok(requirement),
+                None => Err(format!("invalid field selector requirement '{}'", req)),
+            })
+            .collect::<Result<Vec<Requirement>, String>>()?;
+        Ok(Selector { requirements })
+    }
+
+    /// Render the selector in a canonical form, so selectors with the same requirements
+    /// written in a different order or with different spacing are equal
+    pub fn canonical(&self) -> String {
+        let mut requirements: Vec<String> = self.requirements.iter().map(Requirement::canonical).collect();
+        requirements.sort();
+        requirements.dedup();
+        requirements.join(",")
+    }
+
+    pub fn matches_labels(&self, object: &Value) -> bool {
+        let labels = &object["metadata"]["labels"];
+        self.matches(|key| labels[key].as_str().map(|v| v.to_string()))
+    }
+
+    pub fn matches_fields(&self, object: &Value) -> bool {
+        // Missing fields are compared as empty strings, like the API server does
+        self.matches(|key| {
+            let pointer = format!("/{}", key.replace('.', "/"));
+            Some(match object.pointer(&pointer) {
+                Some(Value::String(s)) => s.clone(),
+                Some(Value::Null) | None => String::new(),
+                Some(v) => v.to_string(),
+            })
+        })
+    }
+
+    fn matches<F: Fn(&str) -> Option<String>>(&self, lookup: F) -> bool {
+        self.requirements.iter().all(|req| match req {
+            Requirement::Equals(key, value) => lookup(key).as_ref() == Some(value),
+            Requirement::NotEquals(key, value) => lookup(key).as_ref() != Some(value),
+            Requirement::In(key, values) => lookup(key).map_or(false, |v| values.contains(&v)),
+            Requirement::NotIn(key, values) => lookup(key).map_or(true, |v| !values.contains(&v)),
+            Requirement::Exists(key) => lookup(key).is_some(),
+            Requirement::NotExists(key) => lookup(key).is_none(),
+        })
+    }
+}
+
+impl Requirement {
+    fn canonical(&self) -> String {
+        match self {
+            Requirement::Equals(key, value) => format!("{}={}", key, value),
+            Requirement::NotEquals(key, value) => format!("{}!={}", key, value),
+            Requirement::In(key, values) => format!("{} in ({})", key, sorted(values).join(",")),
+            Requirement::NotIn(key, values) => format!("{} notin ({})", key, sorted(values).join(",")),
+            Requirement::Exists(key) => key.clone(),
+            Requirement::NotExists(key) => format!("!{}", key),
+        }
+    }
+}
+
+fn sorted(values: &[String]) -> Vec<String> {
+    let mut values = values.to_vec();
+    values.sort();
+    values.dedup();
+    values
+}
+
+/// Normalize the label selector, returning it untouched if it cannot be parsed
+pub fn normalize_labels(selector: &str) -> String {
+    Selector::parse_labels(selector)
+        .map(|s| s.canonical())
+        .unwrap_or_else(|_| selector.to_string())
+}
+
+/// Normalize the field selector, returning it untouched if it cannot be parsed
+pub fn normalize_fields(selector: &str) -> String {
+    Selector::parse_fields(selector)
+        .map(|s| s.canonical())
+        .unwrap_or_else(|_| selector.to_string())
+}
+
+/// Split the selector on the commas outside of the value sets
+fn split_requirements(selector: &str) -> Result<Vec<&str>, String> {
+    let mut requirements = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in selector.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Err(format!("unbalanced parenthesis in selector '{}'", selector)),
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                requirements.push(selector[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(format!("unbalanced parenthesis in selector '{}'", selector));
+    }
+    requirements.push(selector[start..].trim());
+    Ok(requirements.into_iter().filter(|req| !req.is_empty()).collect())
+}
+
+fn parse_equality(req: &str) -> Option<Requirement> {
+    if let Some(i) = req.find("!=") {
+        return Some(Requirement::NotEquals(req[..i].trim().to_string(), req[i + 2..].trim().to_string()));
+    }
+    if let Some(i) = req.find("==") {
+        return Some(Requirement::Equals(req[..i].trim().to_string(), req[i + 2..].trim().to_string()));
+    }
+    if let Some(i) = req.find('=') {
+        return Some(Requirement::Equals(req[..i].trim().to_string(), req[i + 1..].trim().to_string()));
+    }
+    None
+}
+
+fn parse_label_requirement(req: &str) -> Result<Requirement, String> {
+    if let Some(requirement) = parse_equality(req) {
+        return Ok(requirement);
+    }
+    if let Some(key) = req.strip_prefix('!') {
+        return Ok(Requirement::NotExists(key.trim().to_string()));
+    }
+    if let Some(i) = req.find('(') {
+        let (head, set) = (req[..i].trim(), &req[i..]);
+        if !set.ends_with(')') {
+            return Err(format!("invalid label selector requirement '{}'", req));
+        }
+        let values: Vec<String> = set[1..set.len() - 1]
+            .split(',')
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect();
+        let mut parts = head.split_whitespace();
+        return match (parts.next(), parts.next(), parts.next()) {
+            (Some(key), Some("in"), None) => Ok(Requirement::In(key.to_string(), values)),
+            (Some(key), Some("notin"), None) => Ok(Requirement::NotIn(key.to_string(), values)),
+            _ => Err(format!("invalid label selector requirement '{}'", req)),
+        };
+    }
+    if req.contains(char::is_whitespace) {
+        return Err(format!("invalid label selector requirement '{}'", req));
+    }
+    Ok(Requirement::Exists(req.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn pod(labels: Value) -> Value {
+        json!({
+            "metadata": { "name": "web-0", "namespace": "default", "labels": labels },
+            "spec": { "nodeName": "node-1" },
+            "status": { "phase": "Running" }
+        })
+    }
+
+    fn matches_labels(selector: &str, labels: Value) -> bool {
+        Selector::parse_labels(selector).unwrap().matches_labels(&pod(labels))
+    }
+
+    #[test]
+    fn equality_requirements() {
+        let labels = json!({ "app": "web", "tier": "frontend" });
+        assert!(matches_labels("app=web", labels.clone()));
+        assert!(matches_labels("app==web,tier=frontend", labels.clone()));
+        assert!(!matches_labels("app=db", labels.clone()));
+        assert!(matches_labels("app!=db", labels.clone()));
+        assert!(!matches_labels("app!=web", labels.clone()));
+        // A missing label is different from any value
+        assert!(matches_labels("env!=prod", labels));
+    }
+
+    #[test]
+    fn set_based_requirements() {
+        let labels = json!({ "app": "web", "env": "prod" });
+        assert!(matches_labels("env in (prod, staging)", labels.clone()));
+        assert!(!matches_labels("env in (dev)", labels.clone()));
+        assert!(matches_labels("env notin (dev,staging)", labels.clone()));
+        assert!(!matches_labels("env notin (prod)", labels.clone()));
+        // A missing label is never in a set, and always not in it
+        assert!(!matches_labels("tier in (frontend)", labels.clone()));
+        assert!(matches_labels("tier notin (frontend)", labels.clone()));
+        assert!(matches_labels("app,!tier", labels.clone()));
+        assert!(!matches_labels("!app", labels.clone()));
+        assert!(!matches_labels("tier", labels));
+    }
+
+    #[test]
+    fn empty_selector_matches_everything() {
+        assert!(matches_labels("", json!({})));
+        assert!(matches_labels(" , ", json!({ "app": "web" })));
+    }
+
+    #[test]
+    fn invalid_label_selectors_are_rejected() {
+        assert!(Selector::parse_labels("env in (prod").is_err());
+        assert!(Selector::parse_labels("env in prod)").is_err());
+        assert!(Selector::parse_labels("env in ((prod)").is_err());
+        assert!(Selector::parse_labels("env within (prod)").is_err());
+        assert!(Selector::parse_labels("app web").is_err());
+        assert!(Selector::parse_fields("status.phase").is_err());
+    }
+
+    #[test]
+    fn canonical_form_ignores_order_and_spacing() {
+        assert_eq!(normalize_labels("tier=frontend, app = web"), normalize_labels("app=web,tier=frontend"));
+        assert_eq!(normalize_labels("env in (b, a),app"), normalize_labels("app , env in (a,b,a)"));
+        assert_eq!(normalize_labels("app==web"), normalize_labels("app=web"));
+        assert_eq!(normalize_fields("status.phase=Running,spec.nodeName=node-1"), "spec.nodeName=node-1,status.phase=Running");
+        assert_ne!(normalize_labels("app=web"), normalize_labels("app!=web"));
+        assert_ne!(normalize_labels("env in (a)"), normalize_labels("env notin (a)"));
+        // Invalid selectors are not normalized, so the API server reports the error
+        assert_eq!(normalize_labels("env in (a"), "env in (a");
+    }
+
+    #[test]
+    fn field_selectors() {
+        let object = pod(json!({}));
+        let matches = |selector: &str| Selector::parse_fields(selector).unwrap().matches_fields(&object);
+        assert!(matches("metadata.name=web-0,metadata.namespace=default"));
+        assert!(matches("spec.nodeName==node-1"));
+        assert!(!matches("status.phase!=Running"));
+        // Missing fields are compared as empty strings
+        assert!(matches("spec.schedulerName="));
+        assert!(!matches("spec.schedulerName!="));
+        assert!(matches("spec.schedulerName!=default-scheduler"));
+    }
+}
@@ -0,0 +1,134 @@
+use super::{run_watch, WatchKey, WatchMessage, MAX_BACKOFF, MIN_BACKOFF};
+use crate::utils::failure_status;
+use futures::future::{AbortHandle, Abortable};
+use serde_json::Value;
+use tokio::sync::mpsc::Sender;
+
+/// Events produced by an informer
+pub(crate) enum InformerEvent {
+    /// The objects were listed, so the events before this one may have been missed
+    Listed { objects: Vec<Value>, resource_version: String },
+    /// A watch event, both parsed and as received from the API server
+    Event { event: Value, raw: Vec<u8> },
+    /// The API server refused to list or watch the resources, the informer is stopped
+    Failed(Value),
+}
+
+/// List the resources of the watch key and then watch them, listing again
+/// when the watch cannot be resumed from the last seen resource version.
+/// The starting resource version of the key is ignored.
+///
+/// Failed lists are retried with an exponential backoff, unless the failure won't go away retrying:
+/// the informer is then stopped with `InformerEvent::Failed`.
+pub(crate) async fn run_informer(
+    mut key: WatchKey,
+    kube_client: kube::Client,
+    mut internal_tx: Sender<(WatchKey, InformerEvent)>,
+) {
+    let informer_key = key.clone();
+    let mut backoff = MIN_BACKOFF;
+
+    loop {
+        match list_objects(&key, &kube_client).await {
+            Ok((objects, resource_version)) => {
+                key.resource_version = resource_version.clone();
+                let listed = InformerEvent::Listed { objects, resource_version };
+                if internal_tx.send((informer_key.clone(), listed)).await.is_err() {
+                    return;
+                }
+
+                let (watch_tx, mut watch_rx) = tokio::sync::mpsc::channel(10);
+                let (abort_handle, abort_registration) = AbortHandle::new_pair();
+                tokio::spawn(Abortable::new(run_watch(key.clone(), kube_client.clone(), watch_tx), abort_registration));
+
+                // The stream ends when the watch cannot continue
+                while let Some((_, message)) = watch_rx.recv().await {
+                    let raw = match message {
+                        WatchMessage::Event(raw) => raw,
+                        WatchMessage::Failed(status) => {
+                            warn!("Cannot watch '{:?}': {}", &informer_key, status["message"]);
+                            let _ = internal_tx.send((informer_key.clone(), InformerEvent::Failed(status))).await;
+                            return;
+                        }
+                    };
+                    let event: Value = match serde_json::from_slice(&raw) {
+                        Ok(event) => event,
+                        Err(e) => {
+                            warn!("Cannot parse watch event for '{:?}': {}", &informer_key, e);
+                            continue;
+                        }
+                    };
+                    if event["type"] == "ERROR" && event["object"]["code"] == 410 {
+                        break;
+                    }
+                    // The watch made progress, so it's not failing repeatedly
+                    backoff = MIN_BACKOFF;
+                    if internal_tx.send((informer_key.clone(), InformerEvent::Event { event, raw })).await.is_err() {
+                        abort_handle.abort();
+                        return;
+                    }
+                }
+                abort_handle.abort();
+            }
+            Err(e) => match permanent_failure_status(&e) {
+                Some(status) => {
+                    warn!("Cannot list '{:?}': {}", &informer_key, e);
+                    let _ = internal_tx.send((informer_key.clone(), InformerEvent::Failed(status))).await;
+                    return;
+                }
+                None => warn!("Cannot list '{:?}': {}", &informer_key, e),
+            },
+        }
+
+        tokio::time::delay_for(backoff).await;
+        backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+    }
+}
+
+/// The status of the failure, if it won't go away retrying
+pub(super) fn permanent_failure_status(e: &anyhow::Error) -> Option<Value> {
+    match e.downcast_ref::<kube::Error>() {
+        Some(kube::Error::Api(error_response)) if is_permanent_failure(error_response.code) => Some(failure_status(
+            error_response.code,
+            &error_response.reason,
+            &error_response.message,
+        )),
+        _ => None,
+    }
+}
+
+/// Client errors, except the ones which may go away retrying
+pub(super) fn is_permanent_failure(code: u16) -> bool {
+    code >= 400 && code < 500 && code != 408 && code != 410 && code != 429
+}
+
+async fn list_objects(key: &WatchKey, kube_client: &kube::Client) -> anyhow::Result<(Vec<Value>, String)> {
+    let mut list: Value = kube_client.request(key.list_request()?).await?;
+    let resource_version = list["metadata"]["resourceVersion"]
+        .as_str()
+        .map(|rv| rv.to_string())
+        .ok_or_else(|| anyhow::anyhow!("List response without resource version"))?;
+
+    // The list items don't carry the type information
+    let objects = match list["items"].take() {
+        Value::Array(items) => items
+            .into_iter()
+            .map(|mut obj| {
+                obj["apiVersion"] = Value::String(key.resource.api_version.clone());
+                obj["kind"] = Value::String(key.resource.kind.clone());
+                obj
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    Ok((objects, resource_version))
+}
+
+/// Key of the object in the informer stores
+pub(crate) fn object_key(object: &Value) -> (String, String) {
+    let metadata = &object["metadata"];
+    (
+        metadata["namespace"].as_str().unwrap_or_default().to_string(),
+        metadata["name"].as_str().unwrap_or_default().to_string(),
+    )
+}
@@ -1,16 +1,67 @@
 use http::Request;
 
+use crate::kube_cache::selector;
+use crate::modules::ModuleIdentity;
 use kube::api::ListParams;
 use std::convert::TryInto;
+use std::time::Duration;
 
+mod informer;
 mod watchers;
 pub use watchers::Watchers;
+pub(crate) use watchers::{run_watch, WatchMessage};
+pub(crate) use informer::{run_informer, object_key, InformerEvent};
+use informer::{is_permanent_failure, permanent_failure_status};
+
+/// Bounds of the delay before retrying a failed list or watch, doubling at each failure
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
 
 #[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub struct WatchKey {
     pub resource_version: String,
     pub resource: kube::Resource,
     pub list_params: ListParams,
+    /// Identity the watch is started with, so watches of modules with different identities are never shared
+    pub identity: ModuleIdentity,
+}
+
+impl WatchKey {
+    /// The key of the shared watch serving this one: the starting resource version and
+    /// the options not changing the watched objects are dropped, and the selectors are normalized.
+    ///
+    /// Normalizing only makes the same selectors written differently share a watch: merging overlapping
+    /// selectors, eg `app=a` and `app in (a,b)`, into a single watch filtered by the host is out of scope.
+    pub fn normalized(&self) -> WatchKey {
+        WatchKey {
+            resource_version: String::new(),
+            resource: self.resource.clone(),
+            list_params: ListParams {
+                label_selector: self.list_params.label_selector.as_deref()
+                    .map(selector::normalize_labels)
+                    .filter(|s| !s.is_empty()),
+                field_selector: self.list_params.field_selector.as_deref()
+                    .map(selector::normalize_fields)
+                    .filter(|s| !s.is_empty()),
+                ..ListParams::default()
+            },
+            identity: self.identity.clone(),
+        }
+    }
+
+    /// Build the watch request starting from the provided resource version
+    pub fn watch_request(&self, resource_version: &str) -> Result<Request<Vec<u8>>, kube::Error> {
+        let mut req = self.resource.watch(&self.list_params, resource_version)?;
+        self.identity.apply(&mut req).map_err(kube::Error::RequestValidation)?;
+        Ok(req)
+    }
+
+    /// Build the list request used to get a fresh resource version
+    pub fn list_request(&self) -> Result<Request<Vec<u8>>, kube::Error> {
+        let mut req = self.resource.list(&self.list_params)?;
+        self.identity.apply(&mut req).map_err(kube::Error::RequestValidation)?;
+        Ok(req)
+    }
 }
 
 impl TryInto<http::Request<Vec<u8>>> for WatchKey {
@@ -1,103 +1,547 @@
-use super::{WatchKey};
+use super::{is_permanent_failure, object_key, permanent_failure_status, run_informer, InformerEvent, WatchKey, MAX_BACKOFF, MIN_BACKOFF};
 use futures::{StreamExt, TryStreamExt};
-use std::collections::HashMap;
-use std::convert::TryInto;
+use futures::future::{AbortHandle, Abortable};
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::{Sender, UnboundedReceiver};
-use crate::abi::dispatcher::{AsyncResult, AsyncType};
-use crate::abi::commands::AbiCommand;
+use crate::abi::dispatcher::{AsyncResult, AsyncType, AsyncValue};
+use crate::abi::commands::{AbiCommand, ExecutorCommand};
+use crate::kube_cache::{self, CacheQuery, Objects};
+use crate::modules::forbidden_status;
+use crate::utils::failure_status;
+use http::StatusCode;
 
+/// Number of events kept by each shared watch to replay them to late receivers
+const HISTORY_SIZE: usize = 1024;
+
+/// Shared watches kept only for the cache are stopped after this time without queries
+const CACHE_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Multiplexes the watches of the modules on shared watches, one for each normalized `WatchKey`.
+///
+/// The store of the shared watch without selectors also serves the cache queries of the resource.
 pub struct Watchers {
-    cache: HashMap<WatchKey, Vec<(String, u64)>>,
-    internal_dispatch_tx: Sender<(WatchKey, Vec<u8>)>,
+    watches: HashMap<WatchKey, SharedWatch>,
+    internal_dispatch_tx: Sender<(WatchKey, InformerEvent)>,
+    /// Receivers whose watch timeout expired
+    timeout_tx: Sender<(String, u64)>,
+}
+
+struct WatchReceiver {
+    controller_name: String,
+    async_request_id: u64,
+    /// The receiver already got the events up to this resource version
+    seen_resource_version: u64,
+}
+
+/// A watch started by the host, with the current state of the watched objects
+struct SharedWatch {
+    receivers: Vec<WatchReceiver>,
+    /// Receivers waiting for the first list, with their starting resource version
+    pending: Vec<(String, u64, String)>,
+    /// Cache queries waiting for the first list
+    pending_queries: Vec<AbiCommand<CacheQuery>>,
+    /// Time of the last cache query, the watch is kept for `CACHE_IDLE_TIMEOUT` after it
+    last_query: Option<Instant>,
+    /// `false` until the first list completes
+    synced: bool,
+    objects: Objects,
+    /// Last events, to replay them to receivers starting from a resource version after `history_start`
+    history: VecDeque<(u64, Vec<u8>)>,
+    history_start: u64,
+    last_resource_version: u64,
+    task: AbortHandle,
+}
+
+impl SharedWatch {
+    fn is_unused(&self) -> bool {
+        self.receivers.is_empty()
+            && self.pending.is_empty()
+            && self.pending_queries.is_empty()
+            && self.last_query.map_or(true, |last_query| last_query.elapsed() >= CACHE_IDLE_TIMEOUT)
+    }
+
+    /// Add the receiver, replaying the events it missed since the starting resource version
+    async fn attach(
+        &mut self,
+        controller_name: String,
+        async_request_id: u64,
+        resource_version: &str,
+        tx: &mut Sender<AsyncResult>,
+    ) -> anyhow::Result<()> {
+        let (replay, seen_resource_version) = match resource_version.parse::<u64>() {
+            // Like the API server, start with the current state, also when the resource version
+            // is not one of the versions the host can compare, eg "" or a version of another API server
+            Ok(0) | Err(_) => {
+                if !resource_version.is_empty() && resource_version != "0" {
+                    debug!(
+                        "Resource version '{}' of receiver ({}, {}) is not a number, starting with the current state",
+                        resource_version, &controller_name, async_request_id
+                    );
+                }
+                let replay = self.objects
+                    .values()
+                    .map(|obj| serde_json::to_vec(&serde_json::json!({ "type": "ADDED", "object": obj })))
+                    .collect::<Result<Vec<Vec<u8>>, serde_json::Error>>()?;
+                (replay, self.last_resource_version)
+            }
+            Ok(rv) if rv >= self.history_start => {
+                let replay = self.history
+                    .iter()
+                    .filter(|(event_rv, _)| *event_rv > rv)
+                    .map(|(_, event)| event.clone())
+                    .collect();
+                (replay, std::cmp::max(rv, self.last_resource_version))
+            }
+            Ok(_) => {
+                debug!(
+                    "Resource version '{}' of receiver ({}, {}) is too old",
+                    resource_version, &controller_name, async_request_id
+                );
+                let gone_event = serde_json::json!({
+                    "type": "ERROR",
+                    "object": failure_status(410, "Expired", "too old resource version")
+                });
+                send_event(tx, &controller_name, async_request_id, Some(serde_json::to_vec(&gone_event)?)).await?;
+                return send_event(tx, &controller_name, async_request_id, None).await;
+            }
+        };
+
+        for event in replay {
+            send_event(tx, &controller_name, async_request_id, Some(event)).await?;
+        }
+        self.receivers.push(WatchReceiver {
+            controller_name,
+            async_request_id,
+            seen_resource_version,
+        });
+        Ok(())
+    }
+
+    async fn dispatch(&mut self, resource_version: Option<u64>, event: Vec<u8>, tx: &mut Sender<AsyncResult>) -> anyhow::Result<()> {
+        for receiver in &self.receivers {
+            if resource_version.map_or(false, |rv| rv <= receiver.seen_resource_version) {
+                continue;
+            }
+            debug!(
+                "Dispatching watch event with id '{}' for controller '{}'",
+                receiver.async_request_id, &receiver.controller_name
+            );
+            send_event(tx, &receiver.controller_name, receiver.async_request_id, Some(event.clone())).await?;
+        }
+        Ok(())
+    }
 }
 
 impl Watchers {
-    fn register_watch(&mut self, command: AbiCommand<WatchKey>, kube_client: kube::Client) {
-        if self.cache.contains_key(&command.value) {
+    async fn register_watch(&mut self, command: AbiCommand<WatchKey>, kube_client: kube::Client, mut tx: Sender<AsyncResult>) -> anyhow::Result<()> {
+        let key = command.value.normalized();
+        let resource_version = command.value.resource_version;
+        let (controller_name, async_request_id) = (command.controller_name, command.async_request_id);
+
+        // The shared watch doesn't end, so the timeout of each receiver is enforced here
+        if let Some(timeout) = command.value.list_params.timeout {
+            let mut timeout_tx = self.timeout_tx.clone();
+            let receiver = (controller_name.clone(), async_request_id);
+            tokio::spawn(async move {
+                tokio::time::delay_for(Duration::from_secs(timeout as u64)).await;
+                let _ = timeout_tx.send(receiver).await;
+            });
+        }
+
+        let shared = self.shared_watch(key.clone(), kube_client);
+        if shared.synced {
             debug!(
                 "Found a watch already started for '{:?}', registering new receiver ({}, {})",
-                &command.value, &command.controller_name, &command.async_request_id
+                &key, &controller_name, &async_request_id
             );
-            self.cache
-                .get_mut(&command.value)
-                .unwrap()
-                .push((command.controller_name, command.async_request_id))
+            shared.attach(controller_name, async_request_id, &resource_version, &mut tx).await?;
         } else {
             debug!(
-                "Starting a new watch for '{:?}', registering new receiver ({}, {})",
-                &command.value, &command.controller_name, &command.async_request_id
+                "Found a watch still listing for '{:?}', registering new receiver ({}, {})",
+                &key, &controller_name, &async_request_id
             );
-            let (watch_key, controller_name, async_request_id) =
-                (command.value, command.controller_name, command.async_request_id);
-            self.cache
-                .insert(watch_key.clone(), vec![(controller_name, async_request_id)]);
+            shared.pending.push((controller_name, async_request_id, resource_version));
+        }
+        Ok(())
+    }
 
-            let mut internal_dispatch_tx = self.internal_dispatch_tx.clone();
+    /// Run the cache query against the store of the shared watch of the resource, starting it if needed
+    async fn query(&mut self, command: AbiCommand<CacheQuery>, kube_client: kube::Client, tx: Sender<AsyncResult>) -> anyhow::Result<()> {
+        let key = command.value.watch_key();
+        let shared = self.shared_watch(key.clone(), kube_client);
+        shared.last_query = Some(Instant::now());
+        if shared.synced {
+            kube_cache::reply(&shared.objects, shared.last_resource_version, command, tx).await
+        } else {
+            debug!(
+                "Watch for '{:?}' not synced yet, enqueuing query ({}, {})",
+                &key, &command.controller_name, &command.async_request_id
+            );
+            shared.pending_queries.push(command);
+            Ok(())
+        }
+    }
 
-            tokio::spawn(async move {
-                let key = watch_key.clone();
-
-                let mut stream = kube_client
-                    .request_events(key.clone().try_into().expect("Watch request"))
-                    .await
-                    .expect("watch events stream")
-                    .boxed();
-                while let Some(event) = stream.try_next().await.expect("watch event") {
-                    internal_dispatch_tx.send((key.clone(), event)).await.unwrap();
-                }
+    /// The shared watch of the key, started if it's not running yet
+    fn shared_watch(&mut self, key: WatchKey, kube_client: kube::Client) -> &mut SharedWatch {
+        let internal_dispatch_tx = &self.internal_dispatch_tx;
+        self.watches.entry(key.clone()).or_insert_with(|| {
+            debug!("Starting a new watch for '{:?}'", &key);
+            let (abort_handle, abort_registration) = AbortHandle::new_pair();
+            tokio::spawn(Abortable::new(
+                run_informer(key, kube_client, internal_dispatch_tx.clone()),
+                abort_registration,
+            ));
+            SharedWatch {
+                receivers: Vec::new(),
+                pending: Vec::new(),
+                pending_queries: Vec::new(),
+                last_query: None,
+                synced: false,
+                objects: Objects::new(),
+                history: VecDeque::new(),
+                history_start: 0,
+                last_resource_version: 0,
+                task: abort_handle,
+            }
+        })
+    }
+
+    /// Remove the receiver, stopping the watch if there are no receivers left
+    fn unregister_watch(&mut self, controller_name: &str, async_request_id: u64) {
+        for shared in self.watches.values_mut() {
+            shared.receivers.retain(|r| !(r.controller_name == controller_name && r.async_request_id == async_request_id));
+            shared.pending.retain(|(name, id, _)| !(name == controller_name && *id == async_request_id));
+        }
+        self.stop_unused_watches();
+    }
+
+    /// Close the stream of the receiver, like the API server does when the watch timeout expires
+    async fn expire_watch(&mut self, controller_name: &str, async_request_id: u64, mut tx: Sender<AsyncResult>) -> anyhow::Result<()> {
+        let registered = self.watches.values().any(|shared| {
+            shared.receivers.iter().any(|r| r.controller_name == controller_name && r.async_request_id == async_request_id)
+                || shared.pending.iter().any(|(name, id, _)| name == controller_name && *id == async_request_id)
+        });
+        if registered {
+            debug!("Watch timeout of receiver ({}, {}) expired", controller_name, async_request_id);
+            self.unregister_watch(controller_name, async_request_id);
+            send_event(&mut tx, controller_name, async_request_id, None).await?;
+        }
+        Ok(())
+    }
+
+    /// Remove all the receivers registered by the provided controller,
+    /// stopping the watches without receivers left
+    fn unregister_controller(&mut self, controller_name: &str) {
+        for shared in self.watches.values_mut() {
+            shared.receivers.retain(|r| r.controller_name != controller_name);
+            shared.pending.retain(|(name, _, _)| name != controller_name);
+        }
+        self.stop_unused_watches();
+    }
+
+    /// Drop the pending cache queries of the controller, all of them if `async_request_id` is `None`
+    fn cancel_queries(&mut self, controller_name: &str, async_request_id: Option<u64>) {
+        for shared in self.watches.values_mut() {
+            shared.pending_queries.retain(|command| {
+                command.controller_name != controller_name
+                    || async_request_id.map_or(false, |id| id != command.async_request_id)
             });
         }
+        self.stop_unused_watches();
+    }
+
+    fn stop_unused_watches(&mut self) {
+        let unused: Vec<WatchKey> = self.watches
+            .iter()
+            .filter(|(_, shared)| shared.is_unused())
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in unused {
+            debug!("Stopping watch '{:?}', no receivers or cache queries left", &key);
+            if let Some(shared) = self.watches.remove(&key) {
+                shared.task.abort();
+            }
+        }
+    }
+
+    /// Send to the receiver an error event with the 403 status, then close the stream
+    async fn deny_watch(command: AbiCommand<WatchKey>, message: String, mut tx: Sender<AsyncResult>) -> anyhow::Result<()> {
+        let error_event = serde_json::json!({
+            "type": "ERROR",
+            "object": forbidden_status(&message)
+        });
+        send_event(&mut tx, &command.controller_name, command.async_request_id, Some(serde_json::to_vec(&error_event)?)).await?;
+        send_event(&mut tx, &command.controller_name, command.async_request_id, None).await
     }
 
-    pub async fn dispatch_event(
-        &self,
+    pub async fn handle_event(
+        &mut self,
         key: WatchKey,
-        event: Vec<u8>,
+        event: InformerEvent,
         mut tx: Sender<AsyncResult>,
     ) -> anyhow::Result<()> {
-        let subs = self.cache.get(&key).ok_or(anyhow::anyhow!(
-            "Cannot find the subscribers list for key {:?}",
-            &key
-        ))?;
-
-        for (controller_name, id) in subs {
-            let watch_event = AsyncResult {
-                controller_name: controller_name.clone(),
-                async_request_id: id.clone(),
-                async_type: AsyncType::Stream,
-                value: Some(event.clone()),
-            };
+        let shared = match self.watches.get_mut(&key) {
+            Some(shared) => shared,
+            None => {
+                // The watch was stopped while this event was in flight
+                debug!("Dropping event for stopped watch '{:?}'", &key);
+                return Ok(());
+            }
+        };
+
+        match event {
+            InformerEvent::Listed { objects, resource_version } => {
+                let resource_version = resource_version.parse::<u64>().unwrap_or_default();
+                shared.objects = objects
+                    .into_iter()
+                    .map(|obj| (object_key(&obj), obj))
+                    .collect();
+                shared.history.clear();
+                shared.history_start = resource_version;
+                shared.last_resource_version = resource_version;
 
-            debug!("Dispatching watch event with id '{}' for controller '{}'", controller_name, id);
+                if shared.synced {
+                    // The receivers missed some events, so they must start again from a fresh list
+                    let gone_event = serde_json::to_vec(&serde_json::json!({
+                        "type": "ERROR",
+                        "object": failure_status(410, "Expired", "the watch was restarted")
+                    }))?;
+                    shared.dispatch(None, gone_event, &mut tx).await?;
+                }
+                shared.synced = true;
+
+                for (controller_name, async_request_id, resource_version) in std::mem::replace(&mut shared.pending, Vec::new()) {
+                    shared.attach(controller_name, async_request_id, &resource_version, &mut tx).await?;
+                }
+                for command in std::mem::replace(&mut shared.pending_queries, Vec::new()) {
+                    kube_cache::reply(&shared.objects, shared.last_resource_version, command, tx.clone()).await?;
+                }
+            }
+            InformerEvent::Event { event, raw } => {
+                let object = &event["object"];
+                let resource_version = object["metadata"]["resourceVersion"]
+                    .as_str()
+                    .and_then(|rv| rv.parse::<u64>().ok());
+
+                match event["type"].as_str() {
+                    Some("ADDED") | Some("MODIFIED") => {
+                        shared.objects.insert(object_key(object), object.clone());
+                    }
+                    Some("DELETED") => {
+                        shared.objects.remove(&object_key(object));
+                    }
+                    _ => {}
+                }
 
-            tx.send(watch_event)
-            .await?;
+                if let Some(rv) = resource_version {
+                    if event["type"] != "BOOKMARK" {
+                        shared.history.push_back((rv, raw.clone()));
+                        if shared.history.len() > HISTORY_SIZE {
+                            if let Some((oldest_rv, _)) = shared.history.pop_front() {
+                                shared.history_start = oldest_rv;
+                            }
+                        }
+                    }
+                    shared.last_resource_version = std::cmp::max(shared.last_resource_version, rv);
+                }
+
+                shared.dispatch(resource_version, raw, &mut tx).await?;
+            }
+            InformerEvent::Failed(status) => {
+                // The watch cannot be started or continued, so the receivers get the error and their streams are closed
+                let shared = self.watches.remove(&key).unwrap();
+                let error_event = serde_json::to_vec(&serde_json::json!({ "type": "ERROR", "object": status }))?;
+                let receivers = shared.receivers
+                    .into_iter()
+                    .map(|r| (r.controller_name, r.async_request_id))
+                    .chain(shared.pending.into_iter().map(|(name, id, _)| (name, id)));
+                for (controller_name, async_request_id) in receivers {
+                    send_event(&mut tx, &controller_name, async_request_id, Some(error_event.clone())).await?;
+                    send_event(&mut tx, &controller_name, async_request_id, None).await?;
+                }
+                let code = StatusCode::from_u16(status["code"].as_u64().unwrap_or(500) as u16)
+                    .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+                for command in shared.pending_queries {
+                    kube_cache::send_response(command, code, status.clone(), tx.clone()).await?;
+                }
+            }
         }
         Ok(())
     }
 
     pub async fn start(
-        mut rx: UnboundedReceiver<AbiCommand<WatchKey>>,
+        mut rx: UnboundedReceiver<ExecutorCommand<WatchKey>>,
+        mut cache_rx: UnboundedReceiver<ExecutorCommand<CacheQuery>>,
         tx: Sender<AsyncResult>,
         kube_client: kube::Client,
     ) -> anyhow::Result<()> {
-        info!("Starting the watch commands listener loop");
+        info!("Starting the watch and cache commands listener loop");
 
         let (internal_tx, mut internal_rx) = tokio::sync::mpsc::channel(10);
+        let (timeout_tx, mut timeout_rx) = tokio::sync::mpsc::channel(10);
         let mut watchers = Watchers {
-            cache: HashMap::new(),
+            watches: HashMap::new(),
             internal_dispatch_tx: internal_tx,
+            timeout_tx,
         };
+        let mut idle_check = tokio::time::interval(CACHE_IDLE_TIMEOUT);
 
         loop {
             tokio::select! {
-                Some(command) = rx.recv() =>
-                    watchers.register_watch(command, kube_client.clone()),
-                Some((watch_key, event_payload)) = internal_rx.recv() =>
-                    watchers.dispatch_event(watch_key, event_payload, tx.clone()).await?,
+                Some(command) = rx.recv() => match command {
+                    ExecutorCommand::Start(command) => watchers.register_watch(command, kube_client.clone(), tx.clone()).await?,
+                    ExecutorCommand::Deny { command, message } => Watchers::deny_watch(command, message, tx.clone()).await?,
+                    ExecutorCommand::Cancel { controller_name, async_request_id } => watchers.unregister_watch(&controller_name, async_request_id),
+                    ExecutorCommand::CancelAll { controller_name } => watchers.unregister_controller(&controller_name),
+                },
+                Some(command) = cache_rx.recv() => match command {
+                    ExecutorCommand::Start(command) => watchers.query(command, kube_client.clone(), tx.clone()).await?,
+                    ExecutorCommand::Deny { command, message } =>
+                        kube_cache::send_response(command, StatusCode::FORBIDDEN, forbidden_status(&message), tx.clone()).await?,
+                    ExecutorCommand::Cancel { controller_name, async_request_id } => watchers.cancel_queries(&controller_name, Some(async_request_id)),
+                    ExecutorCommand::CancelAll { controller_name } => watchers.cancel_queries(&controller_name, None),
+                },
+                _ = idle_check.tick() => watchers.stop_unused_watches(),
+                Some((watch_key, event)) = internal_rx.recv() =>
+                    watchers.handle_event(watch_key, event, tx.clone()).await?,
+                Some((controller_name, async_request_id)) = timeout_rx.recv() =>
+                    watchers.expire_watch(&controller_name, async_request_id, tx.clone()).await?,
                 else => break,
             }
         }
         Ok(())
     }
 }
+
+/// Send an event to the receiver, or close its stream if the event is `None`
+async fn send_event(tx: &mut Sender<AsyncResult>, controller_name: &str, async_request_id: u64, event: Option<Vec<u8>>) -> anyhow::Result<()> {
+    tx.send(AsyncResult {
+        controller_name: controller_name.to_string(),
+        async_request_id,
+        async_type: AsyncType::Stream,
+        value: event.map(AsyncValue::WatchEvent),
+    }).await?;
+    Ok(())
+}
+
+/// Messages sent by `run_watch`
+pub(crate) enum WatchMessage {
+    /// A watch event, as received from the API server
+    Event(Vec<u8>),
+    /// The API server refused the watch with this status, which won't go away retrying, so the watch is stopped
+    Failed(Value),
+}
+
+/// Outcome of a single watch request
+enum WatchEnd {
+    /// The API server closed the stream, the watch can be resumed from the last seen resource version
+    Closed,
+    /// The resource version is too old (410 Gone), the watch must start again from a fresh list
+    Gone,
+    /// The API server replied with an error which won't go away retrying
+    Failed(Value),
+}
+
+/// Run the shared watch, resuming it from the last seen resource version when the API server
+/// closes the stream, and starting again from a fresh list on 410 Gone.
+/// Receivers are notified with the error event on 410, so they can resync,
+/// and with `WatchMessage::Failed` when the watch cannot continue.
+pub(crate) async fn run_watch(
+    key: WatchKey,
+    kube_client: kube::Client,
+    mut internal_dispatch_tx: Sender<(WatchKey, WatchMessage)>,
+) {
+    let mut resource_version = key.resource_version.clone();
+    let mut backoff = MIN_BACKOFF;
+
+    loop {
+        let last_resource_version = resource_version.clone();
+        let end = match watch_once(&key, &kube_client, &mut resource_version, &mut internal_dispatch_tx).await {
+            Ok(end) => end,
+            Err(e) => {
+                warn!("Watch '{:?}' interrupted: {}", &key, e);
+                match permanent_failure_status(&e) {
+                    Some(status) => WatchEnd::Failed(status),
+                    None => WatchEnd::Closed,
+                }
+            }
+        };
+
+        match end {
+            WatchEnd::Closed => {
+                debug!("Resuming watch '{:?}' from resource version '{}'", &key, &resource_version);
+            }
+            WatchEnd::Gone => {
+                debug!("Resource version '{}' of watch '{:?}' is gone, listing again", &resource_version, &key);
+                match list_resource_version(&key, &kube_client).await {
+                    Ok(rv) => resource_version = rv,
+                    Err(e) => warn!("Cannot list '{:?}': {}", &key, e),
+                }
+            }
+            WatchEnd::Failed(status) => {
+                let _ = internal_dispatch_tx.send((key.clone(), WatchMessage::Failed(status))).await;
+                return;
+            }
+        }
+
+        if resource_version != last_resource_version {
+            // The watch made progress, so it's not failing repeatedly
+            backoff = MIN_BACKOFF;
+        }
+        tokio::time::delay_for(backoff).await;
+        backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+    }
+}
+
+/// Execute a single watch request, forwarding the events and tracking the last seen resource version
+async fn watch_once(
+    key: &WatchKey,
+    kube_client: &kube::Client,
+    resource_version: &mut String,
+    internal_dispatch_tx: &mut Sender<(WatchKey, WatchMessage)>,
+) -> anyhow::Result<WatchEnd> {
+    let mut stream = kube_client
+        .request_events(key.watch_request(resource_version)?)
+        .await?
+        .boxed();
+
+    while let Some(event) = stream.try_next().await? {
+        let value: serde_json::Value = serde_json::from_slice(&event)?;
+
+        if value["kind"] == "Status" {
+            // The API server refused the watch, so the body is a Status instead of a stream of events
+            warn!("Watch '{:?}' failed: {}", &key, value["message"]);
+            return match value["code"].as_u64() {
+                Some(code) if is_permanent_failure(code as u16) => Ok(WatchEnd::Failed(value)),
+                _ => Ok(WatchEnd::Closed),
+            };
+        }
+
+        if value["type"] == "ERROR" {
+            internal_dispatch_tx.send((key.clone(), WatchMessage::Event(event))).await?;
+            if value["object"]["code"] == 410 {
+                return Ok(WatchEnd::Gone);
+            }
+            continue;
+        }
+
+        if let Some(rv) = value["object"]["metadata"]["resourceVersion"].as_str() {
+            *resource_version = rv.to_string();
+        }
+        internal_dispatch_tx.send((key.clone(), WatchMessage::Event(event))).await?;
+    }
+
+    Ok(WatchEnd::Closed)
+}
+
+/// List the resources of the watch to get a fresh resource version
+async fn list_resource_version(key: &WatchKey, kube_client: &kube::Client) -> anyhow::Result<String> {
+    let list: serde_json::Value = kube_client.request(key.list_request()?).await?;
+    list["metadata"]["resourceVersion"]
+        .as_str()
+        .map(|rv| rv.to_string())
+        .ok_or_else(|| anyhow::anyhow!("List response without resource version"))
+}
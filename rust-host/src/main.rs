@@ -4,11 +4,13 @@ extern crate log;
 use kube::{Client, Config};
 use std::env;
 use std::path::PathBuf;
-use std::collections::HashMap;
-use tokio::task;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::time::Duration;
 
 mod abi;
 mod kube_watch;
+mod kube_cache;
 mod http;
 mod modules;
 mod delay;
@@ -16,7 +18,7 @@ mod utils;
 
 use crate::abi::AbiConfig;
 use crate::kube_watch::{Watchers};
-use crate::modules::{ControllerModule, ControllerModuleMetadata};
+use crate::modules::ModulesReloader;
 use crate::abi::dispatcher::AsyncResultDispatcher;
 
 fn main() {
@@ -44,76 +46,51 @@ fn main() {
     }
     let path = PathBuf::from(args.remove(1));
     info!("Going to load from {}", path.to_str().unwrap());
-    let mods = ControllerModuleMetadata::load_modules_from_dir(path)
-        .expect("Cannot load the modules from the provided dir");
 
-    runtime.block_on(async {
-        let mut joins = Vec::with_capacity(mods.len());
+    let request_timeout = env::var("REQUEST_TIMEOUT_SECONDS")
+        .map(|secs| Duration::from_secs(secs.parse().expect("REQUEST_TIMEOUT_SECONDS must be a number of seconds")))
+        .unwrap_or(http::DEFAULT_REQUEST_TIMEOUT);
+
+    // In virtual time the delays of a module fire as soon as it's idle, to test controllers without waiting
+    let (clock, idle_rx) = match env::var("VIRTUAL_TIME") {
+        Ok(value) if value == "true" || value == "1" => {
+            info!("Running in virtual time");
+            let (clock, idle_rx) = delay::Clock::virtual_time();
+            (clock, Some(idle_rx))
+        }
+        _ => (delay::Clock::real(), None),
+    };
 
+    runtime.block_on(async {
         let (http_command_tx, http_command_rx) = tokio::sync::mpsc::unbounded_channel();
         let (delay_command_tx, delay_command_rx) = tokio::sync::mpsc::unbounded_channel();
         let (watch_command_tx, watch_command_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (cache_command_tx, cache_command_rx) = tokio::sync::mpsc::unbounded_channel();
         let (async_result_tx, async_result_rx) = tokio::sync::mpsc::channel(10);
+        let (module_event_tx, module_event_rx) = tokio::sync::mpsc::unbounded_channel();
 
-        info!("Starting controllers");
-        for (path, mm, wasm_bytes) in mods {
-            let http_command_sender = http_command_tx.clone();
-            let delay_command_sender = delay_command_tx.clone();
-            let watch_command_sender = watch_command_tx.clone();
-
-            joins.push(task::spawn_blocking(move || {
-                info!(
-                    "Starting module loaded from '{}' with meta {:?}",
-                    path.to_str().unwrap(),
-                    mm
-                );
-                start_controller(mm, wasm_bytes, AbiConfig {
-                    http_command_sender,
-                    delay_command_sender,
-                    watch_command_sender
-                })
-            }));
-        }
-
-        debug!("Joining started controllers");
-
-        let controllers = futures::future::join_all(joins)
-            .await
-            .into_iter()
-            .flat_map(|r| r.into_iter())
-            .map(|r| r.map(|module| (module.name().to_string(), module)))
-            .collect::<anyhow::Result<HashMap<String, ControllerModule>>>()
-            .expect("All controllers started correctly");
+        let abi_config = AbiConfig {
+            http_command_sender: http_command_tx,
+            delay_command_sender: delay_command_tx,
+            watch_command_sender: watch_command_tx,
+            cache_command_sender: cache_command_tx,
+            async_request_counter: Arc::new(AtomicU64::new(0)),
+            clock: clock.clone(),
+        };
 
         // Command executors
-        tokio::spawn(Watchers::start(watch_command_rx, async_result_tx.clone(), kube_client));
-        tokio::spawn(http::start_request_executor(http_command_rx, async_result_tx.clone(), cluster_url, http_client));
-        tokio::spawn(delay::start_delay_executor(delay_command_rx, async_result_tx));
+        tokio::spawn(Watchers::start(watch_command_rx, cache_command_rx, async_result_tx.clone(), kube_client));
+        tokio::spawn(http::start_request_executor(http_command_rx, async_result_tx.clone(), cluster_url, http_client, request_timeout));
+        tokio::spawn(delay::start_delay_executor(delay_command_rx, async_result_tx, clock, idle_rx));
 
         // Result dispatcher
-        tokio::spawn(AsyncResultDispatcher::start(controllers, async_result_rx));
+        tokio::spawn(AsyncResultDispatcher::start(module_event_rx, async_result_rx, abi_config.clone()));
+
+        // Modules loader
+        info!("Starting controllers");
+        tokio::spawn(ModulesReloader::start(path, abi_config, module_event_tx));
 
         tokio::signal::ctrl_c().await.unwrap();
         info!("Closing")
     });
 }
-
-fn start_controller(
-    module_meta: ControllerModuleMetadata,
-    wasm_bytes: Vec<u8>,
-    abi_config: AbiConfig,
-) -> anyhow::Result<ControllerModule> {
-    let module_name = module_meta.name.clone();
-
-    let (module, duration) =
-        execution_time!({ ControllerModule::compile(module_meta, wasm_bytes, abi_config)? });
-    info!(
-        "Compilation time '{}' duration: {} ms",
-        &module_name,
-        duration.as_millis()
-    );
-
-    module.start()?;
-
-    Ok(module)
-}
@@ -0,0 +1,169 @@
+use super::{ControllerModule, ControllerModuleMetadata};
+use crate::abi::AbiConfig;
+use crate::delay::Clock;
+use crate::abi::dispatcher::{AsyncResult, ModuleEvent};
+use std::thread;
+use tokio::sync::mpsc::{Receiver, Sender, UnboundedSender};
+use tokio::sync::oneshot;
+
+/// Size of the queue of messages waiting to be processed by a module
+const MAILBOX_SIZE: usize = 256;
+
+/// Maximum number of async results handed to a module with a single wakeup
+const MAX_BATCH_SIZE: usize = 64;
+
+/// Messages processed by a module executor
+#[derive(Debug)]
+pub enum ModuleMessage {
+    /// Run the controller. Results with an async request id lower than `first_async_request_id`
+    /// were requested by a previous instance of the module and must be ignored
+    Start { first_async_request_id: u64 },
+    /// Wakeup the controller with an async result
+    Wakeup(AsyncResult),
+}
+
+/// Handle to a module running on its own thread.
+///
+/// The wasm `Instance` is created and used only on that thread, so each module is
+/// accessed by a single thread, while different modules run in parallel.
+pub struct ModuleActor {
+    controller_name: String,
+    mailbox: Sender<ModuleMessage>,
+}
+
+impl ModuleActor {
+    /// Spawn the module thread and compile the module on it.
+    /// Returns once the module is compiled, without starting it.
+    pub async fn spawn(
+        meta: ControllerModuleMetadata,
+        wasm_bytes: Vec<u8>,
+        abi_config: AbiConfig,
+        module_event_tx: UnboundedSender<ModuleEvent>,
+    ) -> anyhow::Result<ModuleActor> {
+        let controller_name = meta.name.clone();
+        let (ready_tx, ready_rx) = oneshot::channel();
+        let (mailbox_tx, mailbox_rx) = tokio::sync::mpsc::channel(MAILBOX_SIZE);
+        let clock = abi_config.clock.clone();
+
+        thread::Builder::new()
+            .name(format!("module-{}", &controller_name))
+            .spawn(move || {
+                let module_name = meta.name.clone();
+                let (module, duration) =
+                    crate::execution_time!({ ControllerModule::compile(meta, wasm_bytes, abi_config) });
+                info!(
+                    "Compilation time '{}' duration: {} ms",
+                    &module_name,
+                    duration.as_millis()
+                );
+
+                match module {
+                    Ok(module) => {
+                        if ready_tx.send(Ok(())).is_ok() {
+                            run_mailbox(module, mailbox_rx, module_event_tx, clock)
+                        }
+                    }
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                    }
+                }
+            })?;
+
+        ready_rx.await??;
+
+        Ok(ModuleActor {
+            controller_name,
+            mailbox: mailbox_tx,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.controller_name
+    }
+
+    /// Enqueue a message for the module. Waits if the module queue is full.
+    pub async fn send(&mut self, message: ModuleMessage) -> anyhow::Result<()> {
+        self.mailbox
+            .send(message)
+            .await
+            .map_err(|_| anyhow::anyhow!("Module '{}' is not running anymore", &self.controller_name))
+    }
+}
+
+/// Process the messages of the module until its actor is dropped
+fn run_mailbox(
+    mut module: ControllerModule,
+    mut mailbox_rx: Receiver<ModuleMessage>,
+    module_event_tx: UnboundedSender<ModuleEvent>,
+    clock: Clock,
+) {
+    let controller_name = module.name().to_string();
+    let mut first_async_request_id = 0;
+    let mut failed = false;
+
+    // Message received while batching the async results, to process next
+    let mut next_message = None;
+
+    loop {
+        let message = match next_message.take() {
+            Some(message) => message,
+            None => match futures::executor::block_on(mailbox_rx.recv()) {
+                Some(message) => message,
+                None => break,
+            },
+        };
+        if failed {
+            debug!("Skipping message {:?} for failed controller '{}'", &message, &controller_name);
+            continue;
+        }
+
+        let result = match message {
+            ModuleMessage::Start { first_async_request_id: id } => {
+                first_async_request_id = id;
+                module.start()
+            }
+            ModuleMessage::Wakeup(async_result) => {
+                let (results, next) = drain_wakeups(&mut mailbox_rx, async_result);
+                next_message = next;
+                for result in &results {
+                    clock.request_finished(&controller_name, result.async_request_id);
+                }
+                module.wakeup_batch(results)
+            }
+        };
+
+        if let Err(error) = result {
+            failed = true;
+            let _ = module_event_tx.send(ModuleEvent::Failed {
+                controller_name: controller_name.clone(),
+                first_async_request_id,
+                error,
+            });
+            continue;
+        }
+
+        if next_message.is_none() {
+            match mailbox_rx.try_recv() {
+                Ok(message) => next_message = Some(message),
+                Err(_) => clock.notify_idle(&controller_name),
+            }
+        }
+    }
+
+    debug!("Controller '{}' stopped", &controller_name);
+}
+
+/// Take the async results already queued after the provided one, up to [`MAX_BATCH_SIZE`],
+/// so the module is woken up once for all of them.
+/// Returns the results and the first queued message which is not a wakeup, if any
+fn drain_wakeups(mailbox_rx: &mut Receiver<ModuleMessage>, first: AsyncResult) -> (Vec<AsyncResult>, Option<ModuleMessage>) {
+    let mut results = vec![first];
+    while results.len() < MAX_BATCH_SIZE {
+        match mailbox_rx.try_recv() {
+            Ok(ModuleMessage::Wakeup(async_result)) => results.push(async_result),
+            Ok(message) => return (results, Some(message)),
+            Err(_) => break,
+        }
+    }
+    (results, None)
+}
@@ -0,0 +1,183 @@
+use http::header::{HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+const IMPERSONATE_USER: &str = "impersonate-user";
+const IMPERSONATE_GROUP: &str = "impersonate-group";
+const IMPERSONATE_UID: &str = "impersonate-uid";
+const IMPERSONATE_EXTRA_PREFIX: &str = "impersonate-extra-";
+
+/// Identity the module acts as when talking with the API server, through impersonation.
+///
+/// When no user nor service account is configured, the module acts as the host.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleIdentity {
+    /// Name of the impersonated user
+    #[serde(default)]
+    pub user: Option<String>,
+    /// Groups of the impersonated user
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// Impersonated service account. The API server adds the service account groups by itself
+    #[serde(default)]
+    pub service_account: Option<ServiceAccountRef>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ServiceAccountRef {
+    pub namespace: String,
+    pub name: String,
+}
+
+impl ModuleIdentity {
+    /// Check the configured identity is not ambiguous
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.user.is_some() && self.service_account.is_some() {
+            anyhow::bail!("the identity can configure either a user or a service account, not both")
+        }
+        if self.user.is_none() && self.service_account.is_none() && !self.groups.is_empty() {
+            anyhow::bail!("the identity groups require a user or a service account")
+        }
+        // Check the values can be sent as headers
+        self.apply(&mut http::Request::new(())).map_err(|e| anyhow::anyhow!(e))
+    }
+
+    fn impersonated_user(&self) -> Option<String> {
+        match (&self.user, &self.service_account) {
+            (Some(user), _) => Some(user.clone()),
+            (None, Some(sa)) => Some(format!("system:serviceaccount:{}:{}", sa.namespace, sa.name)),
+            (None, None) => None,
+        }
+    }
+
+    /// Replace the impersonation headers of the request with the ones of this identity.
+    /// The headers set by the module are always dropped, so a module cannot pick its own identity.
+    pub fn apply<T>(&self, req: &mut http::Request<T>) -> Result<(), String> {
+        let headers = req.headers_mut();
+        let module_headers: Vec<HeaderName> = headers
+            .keys()
+            .filter(|name| is_impersonation_header(name))
+            .cloned()
+            .collect();
+        for name in module_headers {
+            headers.remove(&name);
+        }
+
+        let user = match self.impersonated_user() {
+            Some(user) => user,
+            None => return Ok(()),
+        };
+        headers.insert(IMPERSONATE_USER, header_value(&user)?);
+        for group in &self.groups {
+            headers.append(IMPERSONATE_GROUP, header_value(group)?);
+        }
+        Ok(())
+    }
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, String> {
+    HeaderValue::from_str(value).map_err(|_| format!("cannot impersonate '{}': invalid header value", value))
+}
+
+fn is_impersonation_header(name: &HeaderName) -> bool {
+    let name = name.as_str();
+    name == IMPERSONATE_USER || name == IMPERSONATE_GROUP || name == IMPERSONATE_UID || name.starts_with(IMPERSONATE_EXTRA_PREFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Request carrying the impersonation headers a module could set to pick its own identity
+    fn module_request() -> http::Request<()> {
+        http::Request::builder()
+            .uri("/api/v1/pods")
+            .header("Impersonate-User", "system:admin")
+            .header("Impersonate-Group", "system:masters")
+            .header("Impersonate-Uid", "1234")
+            .header("Impersonate-Extra-Scopes", "view")
+            .header("Accept", "application/json")
+            .body(())
+            .unwrap()
+    }
+
+    fn header_values(req: &http::Request<()>, name: &str) -> Vec<String> {
+        req.headers()
+            .get_all(name)
+            .iter()
+            .map(|v| v.to_str().unwrap().to_string())
+            .collect()
+    }
+
+    fn user(name: &str, groups: &[&str]) -> ModuleIdentity {
+        ModuleIdentity {
+            user: Some(name.to_string()),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+            service_account: None,
+        }
+    }
+
+    fn service_account(namespace: &str, name: &str) -> ModuleIdentity {
+        ModuleIdentity {
+            service_account: Some(ServiceAccountRef {
+                namespace: namespace.to_string(),
+                name: name.to_string(),
+            }),
+            ..ModuleIdentity::default()
+        }
+    }
+
+    #[test]
+    fn module_impersonation_headers_are_dropped_without_identity() {
+        let mut req = module_request();
+        ModuleIdentity::default().apply(&mut req).unwrap();
+
+        for name in &["impersonate-user", "impersonate-group", "impersonate-uid", "impersonate-extra-scopes"] {
+            assert!(req.headers().get(*name).is_none(), "{} was not dropped", name);
+        }
+        assert_eq!(header_values(&req, "accept"), vec!["application/json"]);
+    }
+
+    #[test]
+    fn module_impersonation_headers_are_replaced_by_the_identity() {
+        let mut req = module_request();
+        user("controller", &["team-a", "team-b"]).apply(&mut req).unwrap();
+
+        assert_eq!(header_values(&req, "impersonate-user"), vec!["controller"]);
+        assert_eq!(header_values(&req, "impersonate-group"), vec!["team-a", "team-b"]);
+        assert!(req.headers().get("impersonate-uid").is_none());
+        assert!(req.headers().get("impersonate-extra-scopes").is_none());
+        assert_eq!(header_values(&req, "accept"), vec!["application/json"]);
+    }
+
+    #[test]
+    fn service_account_is_impersonated_as_its_username() {
+        let mut req = module_request();
+        service_account("kube-system", "simple-pod").apply(&mut req).unwrap();
+
+        assert_eq!(
+            header_values(&req, "impersonate-user"),
+            vec!["system:serviceaccount:kube-system:simple-pod"]
+        );
+        assert!(req.headers().get("impersonate-group").is_none());
+    }
+
+    #[test]
+    fn invalid_identities_are_rejected() {
+        let mut both = user("controller", &[]);
+        both.service_account = service_account("default", "controller").service_account;
+        assert!(both.validate().is_err());
+
+        let groups_only = ModuleIdentity {
+            groups: vec!["team-a".to_string()],
+            ..ModuleIdentity::default()
+        };
+        assert!(groups_only.validate().is_err());
+
+        assert!(user("bad\nuser", &[]).validate().is_err());
+
+        assert!(ModuleIdentity::default().validate().is_ok());
+        assert!(user("controller", &["team-a"]).validate().is_ok());
+        assert!(service_account("default", "controller").validate().is_ok());
+    }
+}
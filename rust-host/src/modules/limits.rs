@@ -0,0 +1,92 @@
+use crate::abi::AbiError;
+use serde::{Deserialize, Serialize};
+use wasmer_middleware_common::metering::{self, Metering};
+use wasmer_runtime::{compile_with, Instance, Module};
+use wasmer_runtime_core::codegen::{MiddlewareChain, StreamingCompiler};
+use wasmer_singlepass_backend::{ModuleCodeGenerator as SinglePassMCG, SinglePassCompiler};
+
+/// Resource limits of a module
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleLimits {
+    /// Maximum number of 64KiB pages the module linear memory can grow to
+    #[serde(default)]
+    pub max_memory_pages: Option<u32>,
+    /// Execution budget (metering points) for each call into the module
+    #[serde(default)]
+    pub fuel_per_call: Option<u64>,
+    /// Rate limit of the requests sent to the API server
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+}
+
+/// Token bucket rate limit, like the client-go one
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// Requests per second allowed in the long run
+    pub qps: f64,
+    /// Requests which can be sent at once, before throttling to `qps`
+    pub burst: u32,
+}
+
+impl ModuleLimits {
+    /// Compile the module, instrumenting it with the metering middleware if an execution budget is configured
+    pub fn compile(&self, wasm_bytes: &[u8]) -> anyhow::Result<Module> {
+        let module = match self.fuel_per_call {
+            Some(limit) => {
+                let compiler: StreamingCompiler<SinglePassMCG, _, _, _, _> = StreamingCompiler::new(move || {
+                    let mut chain = MiddlewareChain::new();
+                    chain.push(Metering::new(limit));
+                    chain
+                });
+                compile_with(wasm_bytes, &compiler)?
+            }
+            None => compile_with(wasm_bytes, &SinglePassCompiler::new())?,
+        };
+        self.check_memory(&module)?;
+        Ok(module)
+    }
+
+    /// Check the memories of the module cannot grow over `max_memory_pages`.
+    ///
+    /// The runtime enforces the maximum declared by the module, making `memory.grow` fail inside the module,
+    /// so the modules declaring a bigger maximum, or none at all, are rejected.
+    fn check_memory(&self, module: &Module) -> Result<(), AbiError> {
+        let max_memory_pages = match self.max_memory_pages {
+            Some(max_memory_pages) => max_memory_pages,
+            None => return Ok(()),
+        };
+        let info = module.info();
+        let memories = info
+            .memories
+            .iter()
+            .map(|(_, descriptor)| descriptor)
+            .chain(info.imported_memories.iter().map(|(_, (_, descriptor))| descriptor));
+        for descriptor in memories {
+            match descriptor.maximum {
+                Some(maximum) if maximum.0 <= max_memory_pages => {}
+                Some(maximum) => {
+                    return Err(AbiError::LimitExceeded(format!(
+                        "module memory can grow to {} pages, over the limit of {} pages",
+                        maximum.0,
+                        max_memory_pages
+                    )))
+                }
+                None => {
+                    return Err(AbiError::LimitExceeded(format!(
+                        "module memory has no maximum size, over the limit of {} pages",
+                        max_memory_pages
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Refill the execution budget before calling into the module
+    pub fn reset_fuel(&self, instance: &mut Instance) {
+        if self.fuel_per_call.is_some() {
+            metering::set_points_used(instance, 0);
+        }
+    }
+}
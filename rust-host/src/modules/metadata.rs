@@ -1,3 +1,4 @@
+use super::{ModuleIdentity, ModuleLimits, ModulePolicy};
 use crate::abi::AbiVersion;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
@@ -5,20 +6,35 @@ use std::ffi::OsStr;
 use std::fs;
 use std::fs::File;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ControllerModuleMetadata {
     pub name: String,
     pub abi: AbiVersion,
+    #[serde(default)]
+    pub policy: ModulePolicy,
+    #[serde(default)]
+    pub limits: ModuleLimits,
+    #[serde(default)]
+    pub identity: ModuleIdentity,
 }
 
 impl ControllerModuleMetadata {
-    /// Load modules metadata and module bytes from a specific directory
-    pub fn load_modules_from_dir(
-        dir: PathBuf,
-    ) -> Result<Vec<(PathBuf, ControllerModuleMetadata, Vec<u8>)>> {
-        fs::read_dir(dir)?
+    /// Load the module metadata from the provided `.yaml` manifest,
+    /// together with the bytes of the `.wasm` module next to it
+    pub fn load_module(manifest_path: &Path) -> Result<(ControllerModuleMetadata, Vec<u8>)> {
+        let mm: ControllerModuleMetadata = serde_yaml::from_reader(File::open(manifest_path)?)?;
+        mm.identity.validate()?;
+        let mut v: Vec<u8> = Vec::new();
+        let wasm_file_name = manifest_path.with_extension("wasm");
+        File::open(wasm_file_name)?.read_to_end(&mut v)?;
+        Ok((mm, v))
+    }
+
+    /// List the modules manifests in a specific directory
+    pub fn list_manifests_in_dir(dir: &Path) -> Result<Vec<PathBuf>> {
+        Ok(fs::read_dir(dir)?
             .flat_map(|dir_entry| {
                 match dir_entry {
                     Ok(e) => {
@@ -32,13 +48,6 @@ impl ControllerModuleMetadata {
                 }
                 .into_iter()
             })
-            .map(|e| {
-                let mm: ControllerModuleMetadata = serde_yaml::from_reader(File::open(&e)?)?;
-                let mut v: Vec<u8> = Vec::new();
-                let wasm_file_name = e.with_extension("wasm");
-                File::open(wasm_file_name)?.read_to_end(&mut v)?;
-                Ok((e, mm, v))
-            })
-            .collect()
+            .collect())
     }
 }
@@ -1,5 +1,15 @@
+mod actor;
+mod identity;
+mod limits;
 mod metadata;
 mod module;
+mod policy;
+mod reloader;
 
+pub use actor::{ModuleActor, ModuleMessage};
+pub use identity::{ModuleIdentity, ServiceAccountRef};
+pub use limits::{ModuleLimits, RateLimit};
 pub use metadata::ControllerModuleMetadata;
 pub use module::ControllerModule;
+pub use policy::{ModulePolicy, forbidden_status};
+pub use reloader::ModulesReloader;
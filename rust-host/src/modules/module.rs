@@ -1,10 +1,11 @@
 use super::ControllerModuleMetadata;
-use crate::abi::{Abi, AbiConfig, dispatcher::AsyncType};
+use crate::abi::{compat, Abi, AbiConfig, AbiError, AbiFeatures, dispatcher::AsyncResult};
 use wasmer_runtime::*;
-use wasmer_singlepass_backend::SinglePassCompiler;
 
 pub struct ControllerModule {
     meta: ControllerModuleMetadata,
+    /// Abi of the module, with the features the module declared
+    abi: Box<dyn Abi>,
     instance: Instance,
 }
 
@@ -14,15 +15,15 @@ impl ControllerModule {
         wasm_bytes: Vec<u8>,
         abi_config: AbiConfig,
     ) -> anyhow::Result<ControllerModule> {
-        let module = compile_with(&wasm_bytes, &SinglePassCompiler::new())?;
+        let module = meta.limits.compile(&wasm_bytes)?;
 
         // get the version of the WASI module in a non-strict way, meaning we're
         // allowed to have extra imports
         let wasi_version = wasmer_wasi::get_wasi_version(&module, false)
-            .expect("WASI version detected from Wasm module");
+            .ok_or_else(|| AbiError::Instantiation("cannot detect the WASI version of the module".to_string()))?;
 
         // Resolve abi
-        let abi = meta.abi.get_abi();
+        let abi = meta.abi.get_abi(meta.limits.clone());
 
         // WASI imports
         let mut base_imports = wasmer_wasi::generate_import_object_for_version(
@@ -33,32 +34,47 @@ impl ControllerModule {
             vec![],
         );
 
-        base_imports.extend(abi.generate_imports(&meta.name, abi_config));
+        base_imports.extend(abi.generate_imports(&meta, abi_config));
+        let stubbed_imports = compat::stub_unknown_imports(&module, abi.as_ref(), &mut base_imports);
 
         // Compile our webassembly into an `Instance`.
-        let instance = module
+        let mut instance = module
             .instantiate(&base_imports)
-            .unwrap(); //TODO better error management
+            .map_err(|e| AbiError::Instantiation(e.to_string()))?;
 
-        Ok(ControllerModule { meta, instance })
+        // Check the module was built for the abi of the manifest, before running it
+        match abi.read_descriptor(&mut instance)? {
+            Some(descriptor) => {
+                debug!("Module '{}' descriptor: {:?}", &meta.name, &descriptor);
+                descriptor.validate(&meta.abi, abi.as_ref(), &stubbed_imports)?;
+                abi.enable_features(AbiFeatures::from_names(&descriptor.features));
+            }
+            None if stubbed_imports.is_empty() => {
+                warn!("Module '{}' doesn't export an abi descriptor, cannot check its compatibility", &meta.name)
+            }
+            None => {
+                return Err(AbiError::Incompatible(format!(
+                    "imports not provided by the host: {}",
+                    stubbed_imports.join(", ")
+                )).into())
+            }
+        }
+
+        Ok(ControllerModule { meta, abi, instance })
     }
 
     pub fn name(&self) -> &str {
         &self.meta.name
     }
 
-    pub fn start(&self) -> anyhow::Result<()> {
-        let abi = self.meta.abi.get_abi();
-        abi.start_controller(&self.instance)?;
+    pub fn start(&mut self) -> Result<(), AbiError> {
+        self.abi.start_controller(&mut self.instance)?;
         debug!("start_controller completed '{:?}'", &self.meta);
         Ok(())
     }
 
-    pub fn wakeup(&self, async_request_id: u64, async_type: AsyncType, value: Option<Vec<u8>>) -> anyhow::Result<()> {
-        let abi = self.meta.abi.get_abi();
-        Ok(abi.wakeup(&self.instance, async_request_id, async_type, value)?)
+    /// Wake up the module with the results, in a single call into the module if it supports batches
+    pub fn wakeup_batch(&mut self, results: Vec<AsyncResult>) -> Result<(), AbiError> {
+        self.abi.wakeup_batch(&mut self.instance, results)
     }
 }
-
-// https://github.com/bytecodealliance/wasmtime/issues/793#issuecomment-692740254
-unsafe impl Send for ControllerModule {}
@@ -0,0 +1,372 @@
+use crate::kube_watch::WatchKey;
+use crate::kube_cache::CacheQuery;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+
+/// Policy restricting which Kubernetes resources a module may touch.
+///
+/// When no rules are configured, the module is unrestricted.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModulePolicy {
+    #[serde(default)]
+    pub rules: Option<Vec<PolicyRule>>,
+}
+
+/// RBAC-style rule. Every list accepts `*` as wildcard.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyRule {
+    /// API groups, where the core group is `""`
+    #[serde(default)]
+    pub api_groups: Vec<String>,
+    /// Resources, where subresources are expressed as `pods/log`
+    #[serde(default)]
+    pub resources: Vec<String>,
+    /// Verbs, like `get`, `list`, `watch`, `create`, `update`, `patch`, `delete`, `deletecollection`.
+    /// For non resource paths, the verb is the lowercase http method, like `get` or `post`
+    pub verbs: Vec<String>,
+    /// Namespaces. When not configured, the rule applies to all the namespaces and to cluster scoped resources
+    #[serde(default)]
+    pub namespaces: Option<Vec<String>>,
+    /// Non resource paths, like `/version` or `/apis/*`, where a trailing `*` matches any suffix
+    #[serde(default, rename = "nonResourceURLs")]
+    pub non_resource_urls: Vec<String>,
+}
+
+/// Subresources of the namespaces, which are not namespaced resources, like in `/api/v1/namespaces/{name}/status`
+const NAMESPACE_SUBRESOURCES: &[&str] = &["status", "finalize"];
+
+/// Attributes of a request to the Kubernetes API, as defined by the API server authorization layer
+#[derive(Debug, PartialEq)]
+struct RequestAttributes {
+    api_group: String,
+    resource: String,
+    namespace: Option<String>,
+    verb: String,
+}
+
+impl ModulePolicy {
+    /// Check if the module is allowed to execute the provided request.
+    /// Returns the reason of the denial otherwise.
+    pub fn check_request(&self, req: &http::Request<Vec<u8>>) -> Result<(), String> {
+        let rules = match &self.rules {
+            None => return Ok(()),
+            Some(rules) => rules,
+        };
+
+        let attributes = match RequestAttributes::parse(req) {
+            Some(attributes) => attributes,
+            // Non resource requests, like discovery, must be allowed by a `nonResourceURLs` rule
+            None => {
+                let verb = req.method().as_str().to_lowercase();
+                let path = req.uri().path();
+                return if rules.iter().any(|rule| rule.matches_non_resource(&verb, path)) {
+                    Ok(())
+                } else {
+                    Err(format!("module cannot {} path \"{}\"", verb, path))
+                };
+            }
+        };
+
+        if rules.iter().any(|rule| rule.matches(&attributes)) {
+            Ok(())
+        } else {
+            Err(attributes.forbidden_message())
+        }
+    }
+
+    /// Check if the module is allowed to start the provided watch.
+    /// Returns the reason of the denial otherwise.
+    pub fn check_watch(&self, watch_key: &WatchKey) -> Result<(), String> {
+        if self.rules.is_none() {
+            return Ok(());
+        }
+        let req: http::Request<Vec<u8>> = watch_key
+            .clone()
+            .try_into()
+            .map_err(|e: kube::Error| format!("invalid watch request: {}", e))?;
+        self.check_request(&req)
+    }
+
+    /// Check if the module is allowed to run the provided query against the shared cache,
+    /// as if it was the equivalent get or list request.
+    pub fn check_cache_query(&self, query: &CacheQuery) -> Result<(), String> {
+        if self.rules.is_none() {
+            return Ok(());
+        }
+        let req = query
+            .to_request()
+            .map_err(|e| format!("invalid cache query: {}", e))?;
+        self.check_request(&req)
+    }
+}
+
+impl PolicyRule {
+    fn matches(&self, attributes: &RequestAttributes) -> bool {
+        matches_any(&self.api_groups, &attributes.api_group)
+            && matches_any(&self.resources, &attributes.resource)
+            && matches_any(&self.verbs, &attributes.verb)
+            && match (&self.namespaces, &attributes.namespace) {
+                (None, _) => true,
+                (Some(namespaces), Some(ns)) => matches_any(namespaces, ns),
+                (Some(namespaces), None) => namespaces.iter().any(|n| n == "*"),
+            }
+    }
+
+    fn matches_non_resource(&self, verb: &str, path: &str) -> bool {
+        matches_any(&self.verbs, verb)
+            && self.non_resource_urls.iter().any(|url| match url.strip_suffix('*') {
+                Some(prefix) => path.starts_with(prefix),
+                None => url == path,
+            })
+    }
+}
+
+fn matches_any(allowed: &[String], value: &str) -> bool {
+    allowed.iter().any(|a| a == "*" || a == value)
+}
+
+impl RequestAttributes {
+    /// Parse the request following the API server conventions:
+    ///
+    /// - `/api/{version}/[namespaces/{namespace}/]{resource}[/{name}[/{subresource}]]`
+    /// - `/apis/{group}/{version}/[namespaces/{namespace}/]{resource}[/{name}[/{subresource}]]`
+    ///
+    /// Requests to a namespace, like `/api/v1/namespaces/{name}[/status]`, are in the namespace itself.
+    ///
+    /// Returns `None` for non resource requests
+    fn parse(req: &http::Request<Vec<u8>>) -> Option<RequestAttributes> {
+        let segments: Vec<&str> = req
+            .uri()
+            .path()
+            .trim_matches('/')
+            .split('/')
+            .collect();
+
+        let (api_group, mut parts) = match segments.as_slice() {
+            ["api", _version, rest @ ..] if !rest.is_empty() => (String::new(), rest),
+            ["apis", group, _version, rest @ ..] if !rest.is_empty() => (group.to_string(), rest),
+            _ => return None,
+        };
+
+        let mut namespace = None;
+        if parts[0] == "namespaces" && parts.len() >= 2 {
+            namespace = Some(parts[1].to_string());
+            // After the namespace name comes a namespaced resource, unless it's a subresource of the namespace
+            if parts.len() >= 3 && !NAMESPACE_SUBRESOURCES.contains(&parts[2]) {
+                parts = &parts[2..];
+            }
+        }
+
+        let (resource, name) = match parts {
+            [resource] => (resource.to_string(), None),
+            [resource, name] => (resource.to_string(), Some(name)),
+            [resource, name, subresource] => (format!("{}/{}", resource, subresource), Some(name)),
+            _ => return None,
+        };
+
+        let is_watch = req
+            .uri()
+            .query()
+            .map(|q| url::form_urlencoded::parse(q.as_bytes()).any(|(k, v)| k == "watch" && v == "true"))
+            .unwrap_or(false);
+
+        let verb = match (req.method().as_str(), name) {
+            ("GET", _) if is_watch => "watch",
+            ("GET", Some(_)) => "get",
+            ("GET", None) => "list",
+            ("POST", _) => "create",
+            ("PUT", _) => "update",
+            ("PATCH", _) => "patch",
+            ("DELETE", Some(_)) => "delete",
+            ("DELETE", None) => "deletecollection",
+            _ => return None,
+        }
+        .to_string();
+
+        Some(RequestAttributes {
+            api_group,
+            resource,
+            namespace,
+            verb,
+        })
+    }
+
+    fn forbidden_message(&self) -> String {
+        let resource = if self.api_group.is_empty() {
+            format!("\"{}\"", self.resource)
+        } else {
+            format!("\"{}\" in API group \"{}\"", self.resource, self.api_group)
+        };
+        match &self.namespace {
+            Some(ns) => format!("module cannot {} resource {} in the namespace \"{}\"", self.verb, resource, ns),
+            None => format!("module cannot {} resource {} at the cluster scope", self.verb, resource),
+        }
+    }
+}
+
+/// Generate the body of a 403 `Status`, like the one returned by the API server
+pub fn forbidden_status(message: &str) -> serde_json::Value {
+    crate::utils::failure_status(403, "Forbidden", message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, uri: &str) -> http::Request<Vec<u8>> {
+        http::Request::builder().method(method).uri(uri).body(Vec::new()).unwrap()
+    }
+
+    fn attributes(api_group: &str, resource: &str, namespace: Option<&str>, verb: &str) -> RequestAttributes {
+        RequestAttributes {
+            api_group: api_group.to_string(),
+            resource: resource.to_string(),
+            namespace: namespace.map(str::to_string),
+            verb: verb.to_string(),
+        }
+    }
+
+    fn rule(api_groups: &[&str], resources: &[&str], verbs: &[&str], namespaces: Option<&[&str]>) -> PolicyRule {
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+        PolicyRule {
+            api_groups: strings(api_groups),
+            resources: strings(resources),
+            verbs: strings(verbs),
+            namespaces: namespaces.map(strings),
+            non_resource_urls: Vec::new(),
+        }
+    }
+
+    fn parse(method: &str, uri: &str) -> Option<RequestAttributes> {
+        RequestAttributes::parse(&request(method, uri))
+    }
+
+    #[test]
+    fn parse_core_and_group_paths() {
+        assert_eq!(parse("GET", "/api/v1/pods"), Some(attributes("", "pods", None, "list")));
+        assert_eq!(
+            parse("GET", "/api/v1/namespaces/default/pods/nginx"),
+            Some(attributes("", "pods", Some("default"), "get"))
+        );
+        assert_eq!(
+            parse("GET", "/apis/apps/v1/namespaces/default/deployments"),
+            Some(attributes("apps", "deployments", Some("default"), "list"))
+        );
+        assert_eq!(
+            parse("GET", "/apis/slinky.dev/v1alpha1/simplepods"),
+            Some(attributes("slinky.dev", "simplepods", None, "list"))
+        );
+    }
+
+    #[test]
+    fn parse_subresources() {
+        assert_eq!(
+            parse("GET", "/api/v1/namespaces/default/pods/nginx/log"),
+            Some(attributes("", "pods/log", Some("default"), "get"))
+        );
+        assert_eq!(
+            parse("PUT", "/apis/slinky.dev/v1alpha1/namespaces/default/simplepods/sp/status"),
+            Some(attributes("slinky.dev", "simplepods/status", Some("default"), "update"))
+        );
+    }
+
+    #[test]
+    fn parse_namespaces_as_resource() {
+        assert_eq!(parse("GET", "/api/v1/namespaces"), Some(attributes("", "namespaces", None, "list")));
+        assert_eq!(
+            parse("GET", "/api/v1/namespaces/default"),
+            Some(attributes("", "namespaces", Some("default"), "get"))
+        );
+        assert_eq!(
+            parse("PUT", "/api/v1/namespaces/default/status"),
+            Some(attributes("", "namespaces/status", Some("default"), "update"))
+        );
+        assert_eq!(
+            parse("PUT", "/api/v1/namespaces/default/finalize"),
+            Some(attributes("", "namespaces/finalize", Some("default"), "update"))
+        );
+    }
+
+    #[test]
+    fn parse_verbs() {
+        let verb = |method: &str, uri: &str| parse(method, uri).map(|attributes| attributes.verb);
+        assert_eq!(verb("GET", "/api/v1/namespaces/default/pods?watch=true").as_deref(), Some("watch"));
+        assert_eq!(verb("GET", "/api/v1/namespaces/default/pods?watch=false").as_deref(), Some("list"));
+        assert_eq!(verb("GET", "/api/v1/namespaces/default/pods?fieldSelector=metadata.name%3Dnginx&watch=true").as_deref(), Some("watch"));
+        assert_eq!(verb("POST", "/api/v1/namespaces/default/pods").as_deref(), Some("create"));
+        assert_eq!(verb("PATCH", "/api/v1/namespaces/default/pods/nginx").as_deref(), Some("patch"));
+        assert_eq!(verb("DELETE", "/api/v1/namespaces/default/pods/nginx").as_deref(), Some("delete"));
+        assert_eq!(verb("DELETE", "/api/v1/namespaces/default/pods").as_deref(), Some("deletecollection"));
+        assert_eq!(verb("HEAD", "/api/v1/namespaces/default/pods"), None);
+    }
+
+    #[test]
+    fn parse_non_resource_paths() {
+        assert_eq!(parse("GET", "/version"), None);
+        assert_eq!(parse("GET", "/api/v1"), None);
+        assert_eq!(parse("GET", "/apis/apps/v1"), None);
+        assert_eq!(parse("GET", "/api/v1/namespaces/default/pods/nginx/log/extra"), None);
+    }
+
+    #[test]
+    fn rule_matches_groups_resources_and_verbs() {
+        let rule = rule(&[""], &["pods"], &["get", "list"], None);
+        assert!(rule.matches(&attributes("", "pods", Some("default"), "get")));
+        assert!(rule.matches(&attributes("", "pods", None, "list")));
+        assert!(!rule.matches(&attributes("", "pods", Some("default"), "delete")));
+        assert!(!rule.matches(&attributes("apps", "pods", Some("default"), "get")));
+        assert!(!rule.matches(&attributes("", "pods/log", Some("default"), "get")));
+    }
+
+    #[test]
+    fn rule_matches_wildcards() {
+        let rule = rule(&["*"], &["*"], &["*"], None);
+        assert!(rule.matches(&attributes("apps", "deployments", Some("default"), "delete")));
+        assert!(rule.matches(&attributes("", "nodes", None, "watch")));
+    }
+
+    #[test]
+    fn rule_matches_namespaces() {
+        let namespaced = rule(&[""], &["pods"], &["get"], Some(&["default"]));
+        assert!(namespaced.matches(&attributes("", "pods", Some("default"), "get")));
+        assert!(!namespaced.matches(&attributes("", "pods", Some("kube-system"), "get")));
+        // Listing across all the namespaces is cluster scoped
+        assert!(!namespaced.matches(&attributes("", "pods", None, "get")));
+
+        let all_namespaces = rule(&[""], &["pods"], &["get"], Some(&["*"]));
+        assert!(all_namespaces.matches(&attributes("", "pods", None, "get")));
+    }
+
+    #[test]
+    fn check_request_allows_non_resource_paths_only_through_rules() {
+        let policy = ModulePolicy { rules: Some(vec![rule(&[""], &["pods"], &["get"], None)]) };
+        assert!(policy.check_request(&request("GET", "/version")).is_err());
+
+        let mut discovery = rule(&[], &[], &["get"], None);
+        discovery.non_resource_urls = vec!["/version".to_string(), "/apis/*".to_string()];
+        let policy = ModulePolicy { rules: Some(vec![discovery]) };
+        assert!(policy.check_request(&request("GET", "/version")).is_ok());
+        assert!(policy.check_request(&request("GET", "/apis/apps/v1")).is_ok());
+        assert!(policy.check_request(&request("GET", "/healthz")).is_err());
+        assert!(policy.check_request(&request("POST", "/version")).is_err());
+        // Non resource rules never match resources
+        assert!(policy.check_request(&request("GET", "/apis/apps/v1/deployments")).is_err());
+    }
+
+    #[test]
+    fn check_request_without_rules_allows_everything() {
+        let policy = ModulePolicy::default();
+        assert!(policy.check_request(&request("DELETE", "/api/v1/namespaces/default")).is_ok());
+        assert!(policy.check_request(&request("POST", "/version")).is_ok());
+    }
+
+    #[test]
+    fn check_request_reports_denied_resource() {
+        let policy = ModulePolicy { rules: Some(vec![rule(&[""], &["pods"], &["get"], Some(&["default"]))]) };
+        assert_eq!(
+            policy.check_request(&request("DELETE", "/apis/apps/v1/namespaces/default/deployments/nginx")),
+            Err("module cannot delete resource \"deployments\" in API group \"apps\" in the namespace \"default\"".to_string())
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use super::{ControllerModuleMetadata, ModuleActor};
+use crate::abi::AbiConfig;
+use crate::abi::dispatcher::ModuleEvent;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task;
+
+/// Interval between two scans of the modules directory
+const SCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+/// State of a module loaded from the modules directory
+struct LoadedModule {
+    manifest_modified: Option<SystemTime>,
+    wasm_modified: Option<SystemTime>,
+    controller_name: Option<String>,
+}
+
+/// Watches the modules directory and compiles modules when their `.yaml`/`.wasm` pair
+/// is added, replaced or removed, notifying the dispatcher to swap them
+pub struct ModulesReloader {
+    dir: PathBuf,
+    loaded: HashMap<PathBuf, LoadedModule>,
+    abi_config: AbiConfig,
+    tx: UnboundedSender<ModuleEvent>,
+}
+
+impl ModulesReloader {
+    pub async fn start(dir: PathBuf, abi_config: AbiConfig, tx: UnboundedSender<ModuleEvent>) -> anyhow::Result<()> {
+        info!("Starting the modules reloader for dir '{}'", dir.to_str().unwrap());
+
+        let mut reloader = ModulesReloader {
+            dir,
+            loaded: HashMap::new(),
+            abi_config,
+            tx,
+        };
+
+        let mut interval = tokio::time::interval(SCAN_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = reloader.scan().await {
+                error!("Error while scanning the modules dir '{}': {:?}", reloader.dir.to_str().unwrap(), e);
+            }
+        }
+    }
+
+    async fn scan(&mut self) -> anyhow::Result<()> {
+        let manifests = ControllerModuleMetadata::list_manifests_in_dir(&self.dir)?;
+
+        // Look for removed modules
+        let removed: Vec<PathBuf> = self.loaded
+            .keys()
+            .filter(|p| !manifests.contains(p) || !p.with_extension("wasm").exists())
+            .cloned()
+            .collect();
+        for path in removed {
+            if let Some(controller_name) = self.loaded.remove(&path).and_then(|m| m.controller_name) {
+                info!("Module loaded from '{}' was removed", path.to_str().unwrap());
+                self.notify(ModuleEvent::Removed(controller_name))?;
+            }
+        }
+
+        // Look for added or replaced modules
+        for path in manifests {
+            let manifest_modified = modified_time(&path);
+            let wasm_modified = modified_time(&path.with_extension("wasm"));
+            if wasm_modified.is_none() {
+                // The wasm module is not there yet
+                continue;
+            }
+
+            let previous_controller_name = match self.loaded.get(&path) {
+                Some(m) if m.manifest_modified == manifest_modified && m.wasm_modified == wasm_modified => continue,
+                Some(m) => m.controller_name.clone(),
+                None => None,
+            };
+
+            let controller_name = match self.compile(path.clone()).await {
+                Ok(module) => {
+                    let controller_name = module.name().to_string();
+                    if let Some(previous) = previous_controller_name.filter(|n| n != &controller_name) {
+                        self.notify(ModuleEvent::Removed(previous))?;
+                    }
+                    self.notify(ModuleEvent::Loaded(module))?;
+                    Some(controller_name)
+                }
+                Err(e) => {
+                    // Keep the previous version running, if any
+                    error!("Cannot load the module from '{}': {:?}", path.to_str().unwrap(), e);
+                    previous_controller_name
+                }
+            };
+
+            self.loaded.insert(path, LoadedModule {
+                manifest_modified,
+                wasm_modified,
+                controller_name,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn notify(&self, event: ModuleEvent) -> anyhow::Result<()> {
+        self.tx
+            .send(event)
+            .map_err(|_| anyhow::anyhow!("The dispatcher is not running anymore"))
+    }
+
+    async fn compile(&self, path: PathBuf) -> anyhow::Result<ModuleActor> {
+        let (mm, wasm_bytes) = task::spawn_blocking(move || -> anyhow::Result<(ControllerModuleMetadata, Vec<u8>)> {
+            let (mm, wasm_bytes) = ControllerModuleMetadata::load_module(&path)?;
+            info!(
+                "Compiling module loaded from '{}' with meta {:?}",
+                path.to_str().unwrap(),
+                mm
+            );
+            Ok((mm, wasm_bytes))
+        }).await??;
+
+        ModuleActor::spawn(mm, wasm_bytes, self.abi_config.clone(), self.tx.clone()).await
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    path.metadata().and_then(|m| m.modified()).ok()
+}
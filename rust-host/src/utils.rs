@@ -7,3 +7,16 @@ macro_rules! execution_time {
         (res, execution_time)
     }};
 }
+
+/// Build a failure `Status`, like the ones returned by the API server
+pub fn failure_status(code: u16, reason: &str, message: &str) -> serde_json::Value {
+    serde_json::json!({
+        "kind": "Status",
+        "apiVersion": "v1",
+        "metadata": {},
+        "status": "Failure",
+        "message": message,
+        "reason": reason,
+        "code": code
+    })
+}